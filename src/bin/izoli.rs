@@ -1,70 +1,257 @@
-use std::ffi::CString;
+use std::{env, process::exit, str::FromStr};
 
 use izolilib::{
     cgroup::{
-        cgroup::CGroup, cgroup_option::CGroupOption, cpu_limit::CpuLimit,
+        cgroup_config::{CGroupConfig, CGroupHierarchy},
+        cgroup_option::CGroupOption,
+        cpuset::CpuSet,
         limit_value::CGroupLimitValue,
+        units::Bytes,
     },
-    izolibox::{IzoliBox, IzoliBoxOptions, Mount},
+    dev::DevOptions,
+    diagnostics::{self, CheckStatus},
+    izolibox::{IzoliBox, IzoliBoxOptions},
+    layer::{LayerCache, LayerSource},
+    mount::Mount,
+    oci::OciImage,
+    staging::BoxDir,
+    Result,
 };
-use nix::{sys::wait::waitpid, unistd::execvp};
+use nix::sys::wait::waitpid;
 use tracing::Level;
 
 fn main() {
     tracing_subscriber::fmt()
         .with_max_level(Level::TRACE)
+        .with_writer(std::io::stderr)
         .init();
 
-    let cgroup = CGroup::new("izoli").unwrap();
+    let args: Vec<String> = env::args().collect();
 
-    cgroup
-        .add_subtree_control(cgroup.get_controllers().unwrap())
-        .unwrap();
+    let result = match args.get(1).map(|s| s.as_str()) {
+        None => run(&[]),
+        Some("run") => run(&args[2..]),
+        Some("pause") => open_box(&args).and_then(|izolibox| izolibox.pause()),
+        Some("resume") => open_box(&args).and_then(|izolibox| izolibox.resume()),
+        Some("kill") => open_box(&args).and_then(|izolibox| izolibox.kill()),
+        Some("stage") => match &args[2..] {
+            [_, source, dest] => open_box(&args).and_then(|izolibox| izolibox.stage(source, dest)),
+            _ => usage(),
+        },
+        Some("collect") => collect(&args),
+        Some("clean") => open_box(&args).and_then(|izolibox| izolibox.clear_box_dir()),
+        Some("import") => match &args[2..] {
+            [source] => import(source),
+            _ => usage(),
+        },
+        Some("check") => check(args.get(2).map(|s| s.as_str()) == Some("--json")),
+        Some(command) => {
+            eprintln!("unknown command: {}", command);
+            usage();
+        }
+    };
+
+    if let Err(e) = result {
+        eprintln!("izoli: {}", e);
+        exit(1);
+    }
+}
+
+fn usage() -> ! {
+    eprintln!(
+        "usage: izoli [run [--memory <bytes>] [--cpus <cpus>] [--cpu-period <time>] \
+         [--pids <count>] [--image <oci-layout> [--tag <name>]] | pause <id> | resume <id> | kill <id> \
+         | stage <id> <source> <dest> | collect <id> <source> <dest> [--limit <bytes>] \
+         | clean <id> | import <tar|dir> | check [--json]]"
+    );
+    exit(2);
+}
+
+fn parse_id(args: &[String]) -> usize {
+    match args.get(2).map(|id| id.parse()) {
+        Some(Ok(id)) => id,
+        _ => usage(),
+    }
+}
+
+/// Detects the cgroup layout, letting `IZOLI_CGROUP_ROOT` (the cgroup2 mount
+/// point, which also selects the unified hierarchy) and `IZOLI_CGROUP_PARENT`
+/// (the group boxes are created under) override it. `IZOLI_MOVE_STRAY=1`
+/// turns on [`CGroupConfig::move_stray_processes`].
+fn cgroup_config() -> Result<CGroupConfig> {
+    let mut config = CGroupConfig::detect()?;
+
+    if let Some(root) = env::var_os("IZOLI_CGROUP_ROOT") {
+        config.mount_point = root.into();
+        config.hierarchy = CGroupHierarchy::Unified;
+    }
+
+    if let Some(parent) = env::var_os("IZOLI_CGROUP_PARENT") {
+        config.set_parent(parent);
+    }
+
+    if env::var_os("IZOLI_MOVE_STRAY").is_some_and(|value| value == "1") {
+        config.move_stray_processes = true;
+    }
+
+    Ok(config)
+}
+
+fn open_box(args: &[String]) -> Result<IzoliBox> {
+    Ok(IzoliBox::new(
+        parse_id(args),
+        IzoliBoxOptions {
+            cgroup_config: Some(cgroup_config()?),
+            ..Default::default()
+        },
+    ))
+}
+
+/// Copies `source` from the box directory of a box that has exited to
+/// `dest`, refusing more than `--limit` bytes.
+fn collect(args: &[String]) -> Result<()> {
+    let (source, dest, limit) = match &args[2..] {
+        [_, source, dest] => (source, dest, None),
+        [_, source, dest, flag, limit] if flag == "--limit" => {
+            (source, dest, Some(parse_arg::<Bytes>(flag, Some(limit))))
+        }
+        _ => usage(),
+    };
+
+    open_box(args)?.collect(source, dest, limit)
+}
+
+/// Unpacks a tar archive or directory tree into the layer cache and prints
+/// the digest and path of the layer.
+fn import(source: &str) -> Result<()> {
+    let source = if std::path::Path::new(source).is_dir() {
+        LayerSource::Dir(source.into())
+    } else {
+        LayerSource::Tar(source.into())
+    };
+    let layer = LayerCache::default().import(&source)?;
+
+    println!("{} {}", layer.digest, layer.path.display());
+
+    Ok(())
+}
+
+fn check(json: bool) -> Result<()> {
+    let checks = diagnostics::run_checks();
+
+    if json {
+        println!("{}", diagnostics::format_json(&checks));
+    } else {
+        print!("{}", diagnostics::format_table(&checks));
+    }
+
+    if checks.iter().any(|check| check.status == CheckStatus::Fail) {
+        exit(1);
+    }
+
+    Ok(())
+}
+
+/// Parses the value of a command-line option, e.g. `512M` for `--memory`.
+fn parse_arg<T: FromStr>(name: &str, value: Option<&String>) -> T {
+    match value.map(|value| value.parse()) {
+        Some(Ok(value)) => value,
+        _ => {
+            eprintln!("invalid value for {}", name);
+            usage();
+        }
+    }
+}
+
+/// Limits for the box, starting from 1G of memory, 10 processes and no CPU
+/// limit, overridden by `--memory`, `--cpus`, `--cpu-period` and `--pids`.
+fn parse_limits(args: &[String]) -> CGroupOption {
+    let mut option = CGroupOption {
+        cpu_max: Some(CGroupLimitValue::Max),
+        memory_max: Some(CGroupLimitValue::Value(Bytes(1024 * 1024 * 1024))),
+        pids_max: Some(CGroupLimitValue::Value(10)),
+        cpus: Some(CpuSet::from_iter([0])),
+        ..Default::default()
+    };
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--memory" => option.memory_max = Some(parse_arg(arg, args.next())),
+            "--cpus" => option.cpu_max = Some(parse_arg(arg, args.next())),
+            "--cpu-period" => option.cpu_period = Some(parse_arg(arg, args.next())),
+            "--pids" => option.pids_max = Some(parse_arg(arg, args.next())),
+            "--image" | "--tag" => {
+                args.next();
+            }
+            _ => usage(),
+        }
+    }
+
+    option
+}
+
+/// Value of the option `name` in `args`, e.g. the layout for `--image`.
+fn find_arg<'a>(args: &'a [String], name: &str) -> Option<&'a String> {
+    args.iter()
+        .position(|arg| arg == name)
+        .map(|i| args.get(i + 1).unwrap_or_else(|| usage()))
+}
+
+/// Options for the box: the image's root filesystem, environment and command
+/// with `--image`, otherwise read-only host system directories and bash.
+fn base_options(args: &[String]) -> Result<IzoliBoxOptions> {
+    if let Some(layout) = find_arg(args, "--image") {
+        let tag = find_arg(args, "--tag").map(|tag| tag.as_str());
+        let mut options = OciImage::open(layout, tag, &LayerCache::default())?.options()?;
+        if options.command.is_empty() {
+            options.command = vec!["/bin/sh".to_string()];
+        }
+        return Ok(options);
+    }
+
+    Ok(IzoliBoxOptions {
+        mounts: vec![
+            Mount::bind("/bin", "/bin", true, false),
+            Mount::bind("/usr/bin", "/usr/bin", true, false),
+            Mount::bind("/lib", "/lib", true, false),
+            Mount::bind("/lib64", "/lib64", true, false),
+            Mount::bind("/usr/lib", "/usr/lib", true, false),
+            Mount::bind("/usr/lib64", "/usr/lib64", true, false),
+            Mount::bind("/etc", "/etc", true, true),
+        ],
+        command: vec!["/usr/bin/bash".to_string()],
+        ..Default::default()
+    })
+}
+
+fn run(args: &[String]) -> Result<()> {
+    let config = cgroup_config()?;
 
     let izolibox = IzoliBox::new(
         1,
         IzoliBoxOptions {
-            cgroup_option: Some(CGroupOption {
-                cpu_max: Some(CpuLimit {
-                    max: CGroupLimitValue::Max,
-                    period: 100000,
-                }),
-                memory_max: Some(CGroupLimitValue::Value(1024 * 1024 * 1024)),
-                pids_max: Some(CGroupLimitValue::Value(10)),
-                cpus: Some(vec![0]),
+            cgroup_option: Some(parse_limits(args)),
+            cgroup_config: Some(config),
+            new_net: false,
+            dev: Some(DevOptions::default()),
+            box_dir: Some(BoxDir {
+                keep: true,
                 ..Default::default()
             }),
-            new_net: false,
-            mounts: vec![
-                Mount::new("/bin", "/bin", true, false),
-                Mount::new("/usr/bin", "/usr/bin", true, false),
-                Mount::new("/lib", "/lib", true, false),
-                Mount::new("/lib64", "/lib64", true, false),
-                Mount::new("/usr/lib", "/usr/lib", true, false),
-                Mount::new("/usr/lib64", "/usr/lib64", true, false),
-                Mount::new("/etc", "/etc", true, true),
-            ],
+            ..base_options(args)?
         },
     );
 
-    let pid = izolibox
-        .enter(Box::new(|| {
-            let cmd = CString::new("/usr/bin/bash").unwrap();
-            let args: Vec<CString> = vec![];
-
-            #[allow(irrefutable_let_patterns)]
-            if let Err(e) = execvp(&cmd, &args.as_ref()) {
-                eprintln!("execvp failed: {:?}", e);
-                return 127;
-            }
-
-            127
-        }))
-        .unwrap();
+    let pid = izolibox.enter_command()?;
 
     if let Ok(status) = waitpid(pid, None) {
         println!("{:?}", status);
     }
 
     println!("Box real PID: {:?}", pid);
+
+    izolibox.teardown()?;
+
+    Ok(())
 }
@@ -0,0 +1,103 @@
+use std::{os::fd::OwnedFd, path::Path};
+
+use crate::error::Result;
+
+use super::{
+    cgroup::CGroup, cgroup_option::CGroupOption, cgroup_usage::CGroupUsage, cpu_limit::CpuLimit,
+    cpuset::CpuSet, limit_value::CGroupLimitValue, units::Bytes,
+};
+
+/// What a box needs from its cgroup, independent of the hierarchy it lives
+/// in: [`CGroup`] for the unified cgroup2 hierarchy and
+/// [`CGroupV1`](super::cgroup_v1::CGroupV1) for legacy per-controller
+/// hierarchies.
+pub trait CGroupBackend {
+    /// Path of the group relative to the root of its hierarchy.
+    fn get_path(&self) -> &Path;
+
+    fn apply_options(&self, option: &CGroupOption) -> Result<()>;
+
+    /// Opens the group for `clone3` with `CLONE_INTO_CGROUP`, if the
+    /// hierarchy supports it.
+    fn open_fd(&self) -> Result<Option<OwnedFd>>;
+
+    fn add_procs(&self, procs: Vec<u32>) -> Result<()>;
+
+    fn get_procs(&self) -> Result<Vec<u32>>;
+
+    fn get_cpu_max(&self) -> Result<CpuLimit>;
+
+    fn get_memory_max(&self) -> Result<CGroupLimitValue<Bytes>>;
+
+    fn get_pids_max(&self) -> Result<CGroupLimitValue<u32>>;
+
+    fn get_cpuset_cpus(&self) -> Result<CpuSet>;
+
+    fn get_usage(&self) -> Result<CGroupUsage>;
+
+    fn freeze(&self) -> Result<()>;
+
+    fn thaw(&self) -> Result<()>;
+
+    fn kill_all(&self) -> Result<()>;
+
+    fn remove_recursive(&self) -> Result<()>;
+}
+
+impl CGroupBackend for CGroup {
+    fn get_path(&self) -> &Path {
+        &self.path
+    }
+
+    fn apply_options(&self, option: &CGroupOption) -> Result<()> {
+        CGroup::apply_options(self, option)
+    }
+
+    fn open_fd(&self) -> Result<Option<OwnedFd>> {
+        CGroup::open_fd(self).map(Some)
+    }
+
+    fn add_procs(&self, procs: Vec<u32>) -> Result<()> {
+        CGroup::add_procs(self, procs)
+    }
+
+    fn get_procs(&self) -> Result<Vec<u32>> {
+        CGroup::get_procs(self)
+    }
+
+    fn get_cpu_max(&self) -> Result<CpuLimit> {
+        CGroup::get_cpu_max(self)
+    }
+
+    fn get_memory_max(&self) -> Result<CGroupLimitValue<Bytes>> {
+        CGroup::get_memory_max(self)
+    }
+
+    fn get_pids_max(&self) -> Result<CGroupLimitValue<u32>> {
+        CGroup::get_pids_max(self)
+    }
+
+    fn get_cpuset_cpus(&self) -> Result<CpuSet> {
+        CGroup::get_cpuset_cpus(self)
+    }
+
+    fn get_usage(&self) -> Result<CGroupUsage> {
+        CGroup::get_usage(self)
+    }
+
+    fn freeze(&self) -> Result<()> {
+        CGroup::freeze(self)
+    }
+
+    fn thaw(&self) -> Result<()> {
+        CGroup::thaw(self)
+    }
+
+    fn kill_all(&self) -> Result<()> {
+        CGroup::kill_all(self)
+    }
+
+    fn remove_recursive(&self) -> Result<()> {
+        CGroup::remove_recursive(self)
+    }
+}
@@ -1,34 +1,79 @@
 use std::{
     fmt,
     fs::{self, File},
-    io::{Read, Write},
-    path::PathBuf,
+    io::{Read, Seek, SeekFrom, Write},
+    os::fd::{AsFd, OwnedFd},
+    path::{Path, PathBuf},
     str::FromStr,
+    time::{Duration, Instant},
 };
 
+use nix::{
+    errno::Errno,
+    poll::{poll, PollFd, PollFlags, PollTimeout},
+    sys::signal::{kill, Signal},
+    unistd::Pid,
+};
 use tracing::info;
 
+use crate::error::{Error, Result};
+
 use super::{
-    cgroup_option::CGroupOption, cgroup_stat::CGroupStat, controller::Controller,
-    cpu_limit::CpuLimit, limit_value::CGroupLimitValue,
+    cgroup_config::CGroupConfig,
+    cgroup_events::CGroupEvents,
+    cgroup_node::CGroupNode,
+    cgroup_option::CGroupOption,
+    cgroup_stat::CGroupStat,
+    cgroup_type::CGroupType,
+    cgroup_usage::CGroupUsage,
+    controller::Controller,
+    cpu_limit::{CpuLimit, DEFAULT_CPU_PERIOD},
+    cpuset::{CpuSet, CpusetPartition},
+    hugetlb::{HugePageSize, HugetlbEvents},
+    limit_value::CGroupLimitValue,
+    misc::MiscValues,
+    rdma::{RdmaLimit, RdmaLimits},
+    units::{Bytes, TimeSpan},
 };
 
+/// How long freezing, thawing or emptying a cgroup may take before it is
+/// reported as stuck.
+pub(crate) const STATE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Child group that processes found in an ancestor are moved into when
+/// controllers have to be enabled for it.
+const STRAY_LEAF: &str = "leaf";
+
 pub struct CGroup {
+    pub mount_point: PathBuf,
     pub path: PathBuf,
+    remove_on_drop: bool,
 }
 
 impl Drop for CGroup {
     fn drop(&mut self) {
+        if !self.remove_on_drop {
+            return;
+        }
+
         let root = self.get_root_path();
         let _ = fs::remove_dir(root);
     }
 }
 
 impl CGroup {
-    pub fn new(path: &str) -> Result<Self, std::io::Error> {
+    pub fn new(path: impl AsRef<Path>) -> Result<Self> {
+        Self::new_at(CGroupConfig::detect_mount_point()?, path)
+    }
+
+    /// Like [`CGroup::new`], but under an explicit cgroup2 mount point instead
+    /// of the detected one.
+    pub fn new_at(mount_point: impl Into<PathBuf>, path: impl AsRef<Path>) -> Result<Self> {
         info!("creating new cgroup");
         let cgroup = CGroup {
-            path: PathBuf::from(path),
+            mount_point: mount_point.into(),
+            path: Self::relative(path.as_ref()),
+            remove_on_drop: true,
         };
 
         if !cgroup.check_status() {
@@ -39,25 +84,56 @@ impl CGroup {
         Ok(cgroup)
     }
 
-    pub fn get_self_cgroup() -> Result<String, std::io::Error> {
-        let mut file = std::fs::File::open("/proc/self/cgroup")?;
-        let mut buf = String::default();
-        file.read_to_string(&mut buf)?;
+    /// Opens an existing cgroup without creating it. The directory is left in
+    /// place when the returned handle is dropped.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        Self::open_at(CGroupConfig::detect_mount_point()?, path)
+    }
+
+    /// Like [`CGroup::open`], but under an explicit cgroup2 mount point.
+    pub fn open_at(mount_point: impl Into<PathBuf>, path: impl AsRef<Path>) -> Result<Self> {
+        let cgroup = CGroup {
+            mount_point: mount_point.into(),
+            path: Self::relative(path.as_ref()),
+            remove_on_drop: false,
+        };
+
+        if !cgroup.check_status() {
+            return Err(Error::cgroup_io(
+                cgroup.get_root_path(),
+                std::io::ErrorKind::NotFound.into(),
+            ));
+        }
+
+        Ok(cgroup)
+    }
+
+    fn relative(path: &Path) -> PathBuf {
+        path.strip_prefix("/").unwrap_or(path).to_path_buf()
+    }
+
+    pub fn get_self_cgroup() -> Result<String> {
+        let buf = fs::read_to_string("/proc/self/cgroup")
+            .map_err(|e| Error::io("/proc/self/cgroup", e))?;
         info!("self cgroup: {}", buf);
 
         Ok(buf.trim().to_string())
     }
 
-    fn create(&self) -> Result<(), std::io::Error> {
+    fn create(&self) -> Result<()> {
         let root = self.get_root_path();
-        fs::create_dir_all(root)
+        fs::create_dir_all(&root).map_err(|e| Error::cgroup_io(root, e))
     }
 
-    pub fn apply_options(&self, option: &CGroupOption) -> Result<(), std::io::Error> {
+    pub fn apply_options(&self, option: &CGroupOption) -> Result<()> {
         info!("applying cgroup options");
-        if let Some(cpu_max) = &option.cpu_max {
+        if option.cpu_max.is_some() || option.cpu_period.is_some() {
             info!("setting cpu.max");
-            self.set_cpu_max(cpu_max)?;
+            let cpu_limit = CpuLimit::from_quota(
+                option.cpu_max.unwrap_or(CGroupLimitValue::Max),
+                option.cpu_period.unwrap_or(DEFAULT_CPU_PERIOD),
+            );
+            self.set_cpu_max(&cpu_limit)?;
         }
 
         if let Some(memory_max) = &option.memory_max {
@@ -75,33 +151,398 @@ impl CGroup {
             self.set_cpuset_cpus(cpus)?;
         }
 
+        for (size, hugetlb_limit) in option.hugetlb_max.iter().flatten() {
+            info!("setting hugetlb.{}.max", size);
+            self.set_hugetlb_max(*size, hugetlb_limit)?;
+        }
+
+        for (resource, misc_limit) in option.misc_max.iter().flatten() {
+            info!("setting misc.max for {}", resource);
+            self.set_misc_max(resource, misc_limit)?;
+        }
+
+        for rdma_limit in option.rdma_max.iter().flatten() {
+            info!("setting rdma.max for {}", rdma_limit.device);
+            self.set_rdma_max(rdma_limit)?;
+        }
+
+        if let Some(cpus_exclusive) = &option.cpus_exclusive {
+            info!("setting cpuset.cpus.exclusive");
+            self.set_cpuset_cpus_exclusive(cpus_exclusive)?;
+        }
+
+        if let Some(cpus_partition) = &option.cpus_partition {
+            info!("setting cpuset.cpus.partition");
+            self.set_cpuset_cpus_partition(cpus_partition)?;
+        }
+
         Ok(())
     }
 
-    pub fn enter(&self) -> Result<(), std::io::Error> {
+    /// Freezes every process in the cgroup and its descendants, returning once
+    /// `cgroup.events` reports the subtree as frozen.
+    pub fn freeze(&self) -> Result<()> {
+        info!("freezing cgroup");
+        self.write("cgroup.freeze", "1")?;
+        self.wait_frozen(true)
+    }
+
+    /// Thaws a cgroup previously frozen with [`CGroup::freeze`].
+    pub fn thaw(&self) -> Result<()> {
+        info!("thawing cgroup");
+        self.write("cgroup.freeze", "0")?;
+        self.wait_frozen(false)
+    }
+
+    fn wait_frozen(&self, frozen: bool) -> Result<()> {
+        let state = if frozen { "frozen" } else { "thawed" };
+        self.wait_events(state, |events| events.frozen == frozen)
+    }
+
+    /// Waits for `cgroup.events` to satisfy `done`, sleeping in `poll` until
+    /// the kernel notifies a change, and fails once [`STATE_TIMEOUT`] has
+    /// passed. The file is re-read through the polled descriptor, which
+    /// re-arms the notification.
+    fn wait_events(&self, state: &'static str, done: impl Fn(&CGroupEvents) -> bool) -> Result<()> {
+        let path = self.get_file_path("cgroup.events");
+        let mut file = File::open(&path).map_err(|e| Error::cgroup_io(&path, e))?;
+        let deadline = Instant::now() + STATE_TIMEOUT;
+
+        loop {
+            let mut content = String::new();
+            file.seek(SeekFrom::Start(0))
+                .and_then(|_| file.read_to_string(&mut content))
+                .map_err(|e| Error::cgroup_io(&path, e))?;
+            let events =
+                CGroupEvents::from_str(&content).map_err(|_| Error::parse(&path, &content))?;
+            if done(&events) {
+                return Ok(());
+            }
+
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return Err(Error::Timeout {
+                    cgroup: self.path.clone(),
+                    state,
+                });
+            }
+
+            let mut fds = [PollFd::new(file.as_fd(), PollFlags::POLLPRI)];
+            match poll(
+                &mut fds,
+                PollTimeout::try_from(left).unwrap_or(PollTimeout::MAX),
+            ) {
+                Ok(_) | Err(Errno::EINTR) => (),
+                Err(e) => return Err(Error::syscall("poll", path.display().to_string(), e)),
+            }
+        }
+    }
+
+    /// Kills every process in the cgroup and its descendants, returning once
+    /// the subtree is empty. Uses `cgroup.kill` when the kernel provides it and
+    /// otherwise signals each process while the subtree is frozen, so forks
+    /// cannot escape between reading `cgroup.procs` and sending SIGKILL.
+    pub fn kill_all(&self) -> Result<()> {
+        info!("killing all processes in cgroup");
+        if self.get_file_path("cgroup.kill").exists() {
+            self.write("cgroup.kill", "1")?;
+        } else {
+            self.freeze()?;
+            let result = self.kill_descendant_procs();
+            self.thaw()?;
+            result?;
+        }
+
+        self.wait_events("empty", |events| !events.populated)
+    }
+
+    fn kill_descendant_procs(&self) -> Result<()> {
+        for procs in self
+            .get_descendant_paths()?
+            .iter()
+            .map(|path| get_u32_list_at(&path.join("cgroup.procs")))
+        {
+            kill_procs(procs?)?;
+        }
+
+        Ok(())
+    }
+
+    fn get_descendant_paths(&self) -> Result<Vec<PathBuf>> {
+        Self::get_descendant_dirs(self.get_root_path())
+    }
+
+    /// Returns `root` followed by the directories of all of its descendant
+    /// groups, every parent before its children.
+    pub(super) fn get_descendant_dirs(root: PathBuf) -> Result<Vec<PathBuf>> {
+        let mut paths = vec![root];
+        let mut index = 0;
+
+        while index < paths.len() {
+            let children = Self::get_child_paths(&paths[index])?;
+            paths.extend(children);
+            index += 1;
+        }
+
+        Ok(paths)
+    }
+
+    fn get_child_paths(dir: &Path) -> Result<Vec<PathBuf>> {
+        let mut paths = Vec::new();
+
+        for entry in fs::read_dir(dir).map_err(|e| Error::cgroup_io(dir, e))? {
+            let entry = entry.map_err(|e| Error::cgroup_io(dir, e))?;
+            let file_type = entry
+                .file_type()
+                .map_err(|e| Error::cgroup_io(entry.path(), e))?;
+            if file_type.is_dir() {
+                paths.push(entry.path());
+            }
+        }
+        paths.sort();
+
+        Ok(paths)
+    }
+
+    // cgroup tree
+
+    /// Lists the direct child cgroups. The returned handles do not remove
+    /// their directories when dropped.
+    pub fn children(&self) -> Result<Vec<CGroup>> {
+        let children = Self::get_child_paths(&self.get_root_path())?
+            .into_iter()
+            .filter_map(|path| path.file_name().map(|name| self.path.join(name)))
+            .map(|path| CGroup {
+                mount_point: self.mount_point.clone(),
+                path,
+                remove_on_drop: false,
+            })
+            .collect();
+
+        Ok(children)
+    }
+
+    /// Walks the subtree rooted at this cgroup depth-first, in pre-order,
+    /// reporting the statistics of every group including this one.
+    pub fn walk(&self) -> Result<Vec<CGroupNode>> {
+        let mut nodes = Vec::new();
+        self.walk_into(0, &mut nodes)?;
+
+        Ok(nodes)
+    }
+
+    fn walk_into(&self, depth: usize, nodes: &mut Vec<CGroupNode>) -> Result<()> {
+        nodes.push(CGroupNode {
+            path: self.path.clone(),
+            depth,
+            stat: self.get_stat()?,
+            events: self.get_events()?,
+            procs: self.get_procs()?,
+        });
+
+        for child in self.children()? {
+            child.walk_into(depth + 1, nodes)?;
+        }
+
+        Ok(())
+    }
+
+    /// Creates (if needed) the child group `name` as a leaf for processes; no
+    /// controllers are enabled below it. The returned handle removes the
+    /// group when dropped only if it was created by this call.
+    pub fn create_child(&self, name: &str) -> Result<CGroup> {
+        self.inner_create_child(name, false, false)
+    }
+
+    /// Like [`CGroup::create_child`], but enables in the child the same
+    /// controllers this group delegates, so that grandchildren get the same
+    /// controllers as children. Under the "no internal processes" rule the
+    /// child can then only hold processes in its own children.
+    pub fn create_internal(&self, name: &str) -> Result<CGroup> {
+        self.inner_create_child(name, false, true)
+    }
+
+    /// Like [`CGroup::create_child`], but makes the child threaded so that
+    /// individual threads can be placed into it with [`CGroup::add_threads`].
+    /// The threaded controllers this group delegates are enabled in it.
+    pub fn create_threaded_child(&self, name: &str) -> Result<CGroup> {
+        self.inner_create_child(name, true, true)
+    }
+
+    fn inner_create_child(&self, name: &str, threaded: bool, internal: bool) -> Result<CGroup> {
+        let path = self.path.join(name);
+        let mut child = CGroup {
+            mount_point: self.mount_point.clone(),
+            path,
+            remove_on_drop: false,
+        };
+
+        if !child.check_status() {
+            info!("creating child cgroup {}", name);
+            child.create()?;
+            child.remove_on_drop = true;
+        }
+
+        if threaded {
+            child.set_type(CGroupType::Threaded)?;
+        }
+
+        if internal {
+            // Controllers izoli does not know would be written back as
+            // `+unknown`, which the kernel refuses.
+            let controllers: Vec<Controller> = self
+                .get_subtree_control()?
+                .into_iter()
+                .filter(|controller| *controller != Controller::Unknown)
+                .filter(|controller| !threaded || controller.is_threaded())
+                .collect();
+            if !controllers.is_empty() {
+                child.add_subtree_control(controllers)?;
+            }
+        }
+
+        Ok(child)
+    }
+
+    /// Makes `controllers` available in this group by enabling them in the
+    /// `cgroup.subtree_control` of every ancestor, from the mount root down to
+    /// the parent. Processes sitting in an ancestor would violate the "no
+    /// internal processes" rule; they are moved into a `leaf` child group when
+    /// `move_stray_processes` is set, otherwise the ancestor is reported.
+    pub fn enable_controllers(
+        &self,
+        controllers: &[Controller],
+        move_stray_processes: bool,
+    ) -> Result<()> {
+        let mut ancestors: Vec<PathBuf> = self
+            .path
+            .ancestors()
+            .skip(1)
+            .map(Path::to_path_buf)
+            .collect();
+        ancestors.reverse();
+
+        for path in ancestors {
+            let ancestor = CGroup {
+                mount_point: self.mount_point.clone(),
+                path,
+                remove_on_drop: false,
+            };
+
+            let enabled = ancestor.get_subtree_control()?;
+            let missing: Vec<Controller> = controllers
+                .iter()
+                .filter(|controller| !enabled.contains(controller))
+                .copied()
+                .collect();
+            if missing.is_empty() {
+                continue;
+            }
+
+            let available = ancestor.get_controllers()?;
+            if let Some(controller) = missing.iter().find(|c| !available.contains(c)) {
+                return Err(Error::ControllerUnavailable {
+                    controller: *controller,
+                    cgroup: ancestor.path.clone(),
+                });
+            }
+
+            // Only the root of the hierarchy, which has no cgroup.type, may
+            // hold processes and delegate controllers at the same time.
+            if ancestor.get_file_path("cgroup.type").exists() {
+                let procs = ancestor.get_procs()?;
+                if !procs.is_empty() {
+                    if !move_stray_processes {
+                        return Err(Error::InternalProcesses {
+                            cgroup: ancestor.path.clone(),
+                        });
+                    }
+                    ancestor.move_procs_to_leaf(procs)?;
+                }
+            }
+
+            info!("enabling {:?} in {}", missing, ancestor.path.display());
+            ancestor.add_subtree_control(missing).map_err(|e| match e {
+                Error::CGroupIo { source, .. }
+                    if source.raw_os_error() == Some(nix::libc::EBUSY) =>
+                {
+                    Error::InternalProcesses {
+                        cgroup: ancestor.path.clone(),
+                    }
+                }
+                e => e,
+            })?;
+        }
+
+        Ok(())
+    }
+
+    fn move_procs_to_leaf(&self, procs: Vec<u32>) -> Result<()> {
+        info!("moving {:?} into {}", procs, STRAY_LEAF);
+        let leaf = CGroup {
+            mount_point: self.mount_point.clone(),
+            path: self.path.join(STRAY_LEAF),
+            remove_on_drop: false,
+        };
+        if !leaf.check_status() {
+            leaf.create()?;
+        }
+
+        for pid in procs {
+            match leaf.add_procs(vec![pid]) {
+                // The process exited in the meantime.
+                Err(Error::CGroupIo { source, .. })
+                    if source.raw_os_error() == Some(nix::libc::ESRCH) => {}
+                result => result?,
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Kills every process in the subtree and removes its groups bottom-up.
+    pub fn remove_recursive(&self) -> Result<()> {
+        info!("removing cgroup subtree");
+        self.kill_all()?;
+
+        for path in self.get_descendant_paths()?.iter().rev() {
+            fs::remove_dir(path).map_err(|e| Error::cgroup_io(path, e))?;
+        }
+
+        Ok(())
+    }
+
+    /// Opens the cgroup directory, e.g. for `clone3` with `CLONE_INTO_CGROUP`.
+    pub fn open_fd(&self) -> Result<OwnedFd> {
+        let root = self.get_root_path();
+
+        File::open(&root)
+            .map(OwnedFd::from)
+            .map_err(|e| Error::cgroup_io(root, e))
+    }
+
+    pub fn enter(&self) -> Result<()> {
         let pid = std::process::id();
         info!("cgroup enter: {}", pid);
 
         self.add_procs(vec![pid])
     }
 
-    pub fn read(&self, name: &str) -> Result<String, std::io::Error> {
+    pub fn read(&self, name: &str) -> Result<String> {
         info!("reading {}", name);
         let path = self.get_file_path(name);
-        let mut file = File::open(path)?;
-        let mut buf = String::default();
-        file.read_to_string(&mut buf)?;
 
-        Ok(buf)
+        fs::read_to_string(&path).map_err(|e| Error::cgroup_io(path, e))
     }
 
-    pub fn write(&self, name: &str, data: &str) -> Result<(), std::io::Error> {
+    pub fn write(&self, name: &str, data: &str) -> Result<()> {
         info!("writing {} to {}", data, name);
         let path = self.get_file_path(name);
-        let mut file = File::options().append(true).open(path)?;
-        file.write_all(data.as_bytes())?;
-
-        Ok(())
+        File::options()
+            .append(true)
+            .open(&path)
+            .and_then(|mut file| file.write_all(data.as_bytes()))
+            .map_err(|e| Error::cgroup_io(path, e))
     }
 
     pub fn check_status(&self) -> bool {
@@ -117,57 +558,70 @@ impl CGroup {
     }
 
     pub fn get_root_path(&self) -> PathBuf {
-        let cgroup_root = PathBuf::from("/sys/fs/cgroup/");
-
-        cgroup_root.join(&self.path)
+        self.mount_point.join(&self.path)
     }
 
     // cgroup files read
 
-    pub fn get_controllers(&self) -> Result<Vec<Controller>, std::io::Error> {
+    pub fn get_controllers(&self) -> Result<Vec<Controller>> {
         self.inner_get_controllers("cgroup.controllers")
     }
 
-    pub fn get_subtree_control(&self) -> Result<Vec<Controller>, std::io::Error> {
+    pub fn get_subtree_control(&self) -> Result<Vec<Controller>> {
         self.inner_get_controllers("cgroup.subtree_control")
     }
 
-    fn inner_get_controllers(&self, name: &str) -> Result<Vec<Controller>, std::io::Error> {
+    fn inner_get_controllers(&self, name: &str) -> Result<Vec<Controller>> {
         let controllers = self
             .read(name)?
-            .trim()
-            .split(" ")
+            .split_whitespace()
             .map(|controller| Controller::from_str(controller).unwrap_or(Controller::Unknown))
             .collect();
 
         Ok(controllers)
     }
 
-    pub fn get_procs(&self) -> Result<Vec<u32>, std::io::Error> {
+    pub fn get_procs(&self) -> Result<Vec<u32>> {
         self.get_u32_list("cgroup.procs")
     }
 
-    pub fn get_threads(&self) -> Result<Vec<u32>, std::io::Error> {
+    pub fn get_threads(&self) -> Result<Vec<u32>> {
         self.get_u32_list("cgroup.threads")
     }
 
-    pub fn get_stat(&self) -> Result<CGroupStat, std::io::Error> {
-        let stat = self.read("cgroup.stat")?;
+    pub fn get_stat(&self) -> Result<CGroupStat> {
+        self.parse("cgroup.stat")
+    }
+
+    pub fn get_events(&self) -> Result<CGroupEvents> {
+        self.parse("cgroup.events")
+    }
+
+    pub fn get_freeze(&self) -> Result<bool> {
+        Ok(self.read("cgroup.freeze")?.trim() == "1")
+    }
 
-        Ok(CGroupStat::from_str(&stat).unwrap())
+    /// Reads `cgroup.type`. The root cgroup has no such file and is reported
+    /// as a domain.
+    pub fn get_type(&self) -> Result<CGroupType> {
+        if !self.get_file_path("cgroup.type").exists() {
+            return Ok(CGroupType::Domain);
+        }
+
+        self.parse("cgroup.type")
     }
 
-    pub fn get_max_depth(&self) -> Result<CGroupLimitValue<u64>, std::io::Error> {
+    pub fn get_max_depth(&self) -> Result<CGroupLimitValue<u64>> {
         self.get_limit_value("cgroup.max.depth")
     }
 
-    pub fn get_max_descendants(&self) -> Result<CGroupLimitValue<u64>, std::io::Error> {
+    pub fn get_max_descendants(&self) -> Result<CGroupLimitValue<u64>> {
         self.get_limit_value("cgroup.max.descendants")
     }
 
     // cgroup files write
 
-    pub fn add_subtree_control(&self, controllers: Vec<Controller>) -> Result<(), std::io::Error> {
+    pub fn add_subtree_control(&self, controllers: Vec<Controller>) -> Result<()> {
         let to_write = controllers
             .iter()
             .map(|controller| format!("+{}", controller))
@@ -179,10 +633,7 @@ impl CGroup {
         Ok(())
     }
 
-    pub fn remove_subtree_control(
-        &self,
-        controllers: Vec<Controller>,
-    ) -> Result<(), std::io::Error> {
+    pub fn remove_subtree_control(&self, controllers: Vec<Controller>) -> Result<()> {
         let to_write = controllers
             .iter()
             .map(|controller| format!("-{}", controller))
@@ -194,33 +645,91 @@ impl CGroup {
         Ok(())
     }
 
-    pub fn set_max_depth(&self, max: CGroupLimitValue<u64>) -> Result<(), std::io::Error> {
+    pub fn set_max_depth(&self, max: CGroupLimitValue<u64>) -> Result<()> {
         self.write_value("cgroup.max.depth", max)
     }
 
-    pub fn set_max_descendants(&self, max: CGroupLimitValue<u64>) -> Result<(), std::io::Error> {
+    pub fn set_max_descendants(&self, max: CGroupLimitValue<u64>) -> Result<()> {
         self.write_value("cgroup.max.descendants", max)
     }
 
-    pub fn add_procs(&self, procs: Vec<u32>) -> Result<(), std::io::Error> {
-        self.write_list("cgroup.procs", procs)
+    /// Changes `cgroup.type`. The kernel only accepts turning a cgroup into a
+    /// threaded one, which also turns its parent into a threaded domain;
+    /// anything else, or a change the kernel refuses, is reported as
+    /// [`Error::InvalidTypeTransition`].
+    pub fn set_type(&self, cgroup_type: CGroupType) -> Result<()> {
+        let current = self.get_type()?;
+        if current == cgroup_type {
+            return Ok(());
+        }
+
+        let invalid = || Error::InvalidTypeTransition {
+            cgroup: self.path.clone(),
+            from: current,
+            to: cgroup_type,
+        };
+
+        if cgroup_type != CGroupType::Threaded {
+            return Err(invalid());
+        }
+
+        self.write_value("cgroup.type", cgroup_type)
+            .map_err(|e| match e {
+                Error::CGroupIo { ref source, .. }
+                    if matches!(
+                        source.raw_os_error(),
+                        Some(nix::libc::EOPNOTSUPP | nix::libc::EINVAL)
+                    ) =>
+                {
+                    invalid()
+                }
+                e => e,
+            })
+    }
+
+    /// Moves processes into this cgroup. The kernel takes one PID per write.
+    pub fn add_procs(&self, procs: Vec<u32>) -> Result<()> {
+        self.write_each("cgroup.procs", procs)
     }
 
-    pub fn add_threads(&self, threads: Vec<u32>) -> Result<(), std::io::Error> {
-        self.write_list("cgroup.threads", threads)
+    /// Moves individual threads into this cgroup, which must be threaded or
+    /// the domain of a threaded subtree they already belong to.
+    pub fn add_threads(&self, threads: Vec<u32>) -> Result<()> {
+        self.write_each("cgroup.threads", threads)
     }
 
     // cpu read
 
-    pub fn get_cpu_max(&self) -> Result<CpuLimit, std::io::Error> {
-        let max = self.read("cpu.max")?;
+    pub fn get_cpu_max(&self) -> Result<CpuLimit> {
+        self.parse("cpu.max")
+    }
+
+    /// Reads the CPU time consumed by the group from `usage_usec` in
+    /// `cpu.stat`.
+    pub fn get_cpu_usage(&self) -> Result<TimeSpan> {
+        let content = self.read("cpu.stat")?;
+
+        content
+            .lines()
+            .find_map(|line| line.strip_prefix("usage_usec "))
+            .and_then(|usage| u64::from_str(usage.trim()).ok())
+            .map(TimeSpan::from_micros)
+            .ok_or_else(|| Error::parse(self.get_file_path("cpu.stat"), &content))
+    }
 
-        Ok(CpuLimit::from_str(&max).unwrap())
+    /// Collects CPU, memory and pids usage, leaving out controllers that are
+    /// not enabled in the group.
+    pub fn get_usage(&self) -> Result<CGroupUsage> {
+        Ok(CGroupUsage {
+            cpu_usage: Some(self.get_cpu_usage()?),
+            memory_current: self.parse_optional("memory.current")?,
+            pids_current: self.parse_optional("pids.current")?,
+        })
     }
 
     // cpu write
 
-    pub fn set_cpu_max(&self, cpu_limit: &CpuLimit) -> Result<(), std::io::Error> {
+    pub fn set_cpu_max(&self, cpu_limit: &CpuLimit) -> Result<()> {
         let to_write = cpu_limit.to_string();
 
         self.write("cpu.max", &to_write)
@@ -228,34 +737,35 @@ impl CGroup {
 
     // memory read
 
-    pub fn get_memory_max(&self) -> Result<CGroupLimitValue<u32>, std::io::Error> {
-        let max = self.read("memory.max")?;
+    pub fn get_memory_max(&self) -> Result<CGroupLimitValue<Bytes>> {
+        self.parse("memory.max")
+    }
 
-        Ok(CGroupLimitValue::from_str(&max).unwrap())
+    pub fn get_memory_current(&self) -> Result<Bytes> {
+        self.parse("memory.current")
     }
 
     // memory write
 
-    pub fn set_memory_max(
-        &self,
-        memory_limit: &CGroupLimitValue<u32>,
-    ) -> Result<(), std::io::Error> {
-        let to_write = memory_limit.to_string();
+    pub fn set_memory_max(&self, memory_limit: &CGroupLimitValue<Bytes>) -> Result<()> {
+        let to_write = memory_limit.map(Bytes::as_u64).to_string();
 
         self.write("memory.max", &to_write)
     }
 
     // pids read
 
-    pub fn get_pids_max(&self) -> Result<CGroupLimitValue<u32>, std::io::Error> {
-        let max = self.read("pids.max")?;
+    pub fn get_pids_max(&self) -> Result<CGroupLimitValue<u32>> {
+        self.parse("pids.max")
+    }
 
-        Ok(CGroupLimitValue::from_str(&max).unwrap())
+    pub fn get_pids_current(&self) -> Result<u64> {
+        self.parse("pids.current")
     }
 
     // pids write
 
-    pub fn set_pids_max(&self, pids_limit: &CGroupLimitValue<u32>) -> Result<(), std::io::Error> {
+    pub fn set_pids_max(&self, pids_limit: &CGroupLimitValue<u32>) -> Result<()> {
         let to_write = pids_limit.to_string();
 
         self.write("pids.max", &to_write)
@@ -263,17 +773,178 @@ impl CGroup {
 
     // cpuset read
 
-    pub fn get_cpuset_cpus(&self) -> Result<Vec<u32>, std::io::Error> {
-        self.get_u32_list("cpuset.cpus")
+    pub fn get_cpuset_cpus(&self) -> Result<CpuSet> {
+        self.parse("cpuset.cpus")
+    }
+
+    pub fn get_cpuset_cpus_effective(&self) -> Result<CpuSet> {
+        self.parse("cpuset.cpus.effective")
+    }
+
+    pub fn get_cpuset_cpus_exclusive(&self) -> Result<CpuSet> {
+        self.parse("cpuset.cpus.exclusive")
+    }
+
+    pub fn get_cpuset_cpus_exclusive_effective(&self) -> Result<CpuSet> {
+        self.parse("cpuset.cpus.exclusive.effective")
+    }
+
+    pub fn get_cpuset_cpus_partition(&self) -> Result<CpusetPartition> {
+        self.parse("cpuset.cpus.partition")
+    }
+
+    pub fn get_cpuset_mems(&self) -> Result<CpuSet> {
+        self.parse("cpuset.mems")
+    }
+
+    pub fn get_cpuset_mems_effective(&self) -> Result<CpuSet> {
+        self.parse("cpuset.mems.effective")
     }
 
     // cpuset write
 
-    pub fn set_cpuset_cpus(&self, cpus: &Vec<u32>) -> Result<(), std::io::Error> {
-        self.write_list("cpuset.cpus", cpus.clone())
+    pub fn set_cpuset_cpus(&self, cpus: &CpuSet) -> Result<()> {
+        self.write_value("cpuset.cpus", cpus)
+    }
+
+    /// Claims CPUs exclusively: no sibling cgroup may list them in its own
+    /// `cpuset.cpus.exclusive`. They must be a subset of the parent's.
+    pub fn set_cpuset_cpus_exclusive(&self, cpus: &CpuSet) -> Result<()> {
+        self.write_value("cpuset.cpus.exclusive", cpus)
     }
 
-    fn write_value<T>(&self, name: &str, value: T) -> Result<(), std::io::Error>
+    /// Turns the cpuset into a partition root (or back into a member). The
+    /// kernel accepts partitions it cannot honor and marks them invalid;
+    /// those are reported as [`Error::InvalidPartition`], as are requests
+    /// for one of the invalid states.
+    pub fn set_cpuset_cpus_partition(&self, partition: &CpusetPartition) -> Result<()> {
+        if partition.is_invalid() {
+            return Err(Error::InvalidPartition {
+                cgroup: self.path.clone(),
+                partition: partition.clone(),
+            });
+        }
+        self.write_value("cpuset.cpus.partition", partition)?;
+
+        match self.get_cpuset_cpus_partition()? {
+            actual if actual.is_invalid() => Err(Error::InvalidPartition {
+                cgroup: self.path.clone(),
+                partition: actual,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn set_cpuset_mems(&self, mems: &CpuSet) -> Result<()> {
+        self.write_value("cpuset.mems", mems)
+    }
+
+    // hugetlb read
+
+    /// Lists the huge page sizes the hugetlb controller exposes in this group,
+    /// discovered from its `hugetlb.<size>.max` files.
+    pub fn get_hugetlb_sizes(&self) -> Result<Vec<HugePageSize>> {
+        let root = self.get_root_path();
+        let mut sizes = Vec::new();
+
+        for entry in fs::read_dir(&root).map_err(|e| Error::cgroup_io(&root, e))? {
+            let entry = entry.map_err(|e| Error::cgroup_io(&root, e))?;
+            let name = entry.file_name();
+            let size = name
+                .to_str()
+                .and_then(|name| name.strip_prefix("hugetlb."))
+                .and_then(|name| name.strip_suffix(".max"))
+                .and_then(|size| HugePageSize::from_str(size).ok());
+
+            if let Some(size) = size {
+                sizes.push(size);
+            }
+        }
+        sizes.sort();
+
+        Ok(sizes)
+    }
+
+    pub fn get_hugetlb_max(&self, size: HugePageSize) -> Result<CGroupLimitValue<Bytes>> {
+        self.parse(&format!("hugetlb.{}.max", size))
+    }
+
+    pub fn get_hugetlb_current(&self, size: HugePageSize) -> Result<Bytes> {
+        self.parse(&format!("hugetlb.{}.current", size))
+    }
+
+    pub fn get_hugetlb_events(&self, size: HugePageSize) -> Result<HugetlbEvents> {
+        self.parse(&format!("hugetlb.{}.events", size))
+    }
+
+    // hugetlb write
+
+    pub fn set_hugetlb_max(
+        &self,
+        size: HugePageSize,
+        hugetlb_limit: &CGroupLimitValue<Bytes>,
+    ) -> Result<()> {
+        self.write_value(
+            &format!("hugetlb.{}.max", size),
+            hugetlb_limit.map(Bytes::as_u64),
+        )
+    }
+
+    // misc read
+
+    /// Reads `misc.capacity`, the amount of each resource on the host. Only
+    /// present in the root cgroup.
+    pub fn get_misc_capacity(&self) -> Result<MiscValues<u64>> {
+        self.parse("misc.capacity")
+    }
+
+    pub fn get_misc_max(&self) -> Result<MiscValues<CGroupLimitValue<u64>>> {
+        self.parse("misc.max")
+    }
+
+    pub fn get_misc_current(&self) -> Result<MiscValues<u64>> {
+        self.parse("misc.current")
+    }
+
+    /// Reads `misc.events`, counting for each resource how often its limit
+    /// was hit.
+    pub fn get_misc_events(&self) -> Result<MiscValues<u64>> {
+        let events: MiscValues<u64> = self.parse("misc.events")?;
+        let values = events
+            .values
+            .into_iter()
+            .map(|(key, value)| {
+                let resource = key.strip_suffix(".max").unwrap_or(&key).to_string();
+                (resource, value)
+            })
+            .collect();
+
+        Ok(MiscValues { values })
+    }
+
+    // misc write
+
+    pub fn set_misc_max(&self, resource: &str, misc_limit: &CGroupLimitValue<u64>) -> Result<()> {
+        self.write("misc.max", &format!("{} {}", resource, misc_limit))
+    }
+
+    // rdma read
+
+    pub fn get_rdma_max(&self) -> Result<RdmaLimits> {
+        self.parse("rdma.max")
+    }
+
+    pub fn get_rdma_current(&self) -> Result<RdmaLimits> {
+        self.parse("rdma.current")
+    }
+
+    // rdma write
+
+    pub fn set_rdma_max(&self, rdma_limit: &RdmaLimit) -> Result<()> {
+        self.write_value("rdma.max", rdma_limit)
+    }
+
+    fn write_value<T>(&self, name: &str, value: T) -> Result<()>
     where
         T: fmt::Display,
     {
@@ -281,37 +952,328 @@ impl CGroup {
         Ok(())
     }
 
-    fn write_list<T>(&self, name: &str, value: Vec<T>) -> Result<(), std::io::Error>
+    fn write_each<T>(&self, name: &str, values: Vec<T>) -> Result<()>
     where
         T: fmt::Display,
     {
-        let to_write = value
-            .iter()
-            .map(|v| v.to_string())
-            .collect::<Vec<String>>()
-            .join("\n");
-
-        self.write(name, &to_write)?;
+        for value in values {
+            self.write_value(name, value)?;
+        }
 
         Ok(())
     }
 
-    fn get_u32_list(&self, name: &str) -> Result<Vec<u32>, std::io::Error> {
-        let procs = self
-            .read(name)?
-            .lines()
-            .map(|proc| u32::from_str(proc.trim()).unwrap())
-            .collect();
-
-        Ok(procs)
+    fn get_u32_list(&self, name: &str) -> Result<Vec<u32>> {
+        get_u32_list_at(&self.get_file_path(name))
     }
 
-    fn get_limit_value<T>(&self, name: &str) -> Result<CGroupLimitValue<T>, std::io::Error>
+    fn get_limit_value<T>(&self, name: &str) -> Result<CGroupLimitValue<T>>
     where
         T: FromStr + fmt::Display,
     {
-        let value = self.read(name)?;
+        self.parse(name)
+    }
+
+    fn parse<T: FromStr>(&self, name: &str) -> Result<T> {
+        let content = self.read(name)?;
+
+        T::from_str(&content).map_err(|_| Error::parse(self.get_file_path(name), &content))
+    }
+
+    /// Like [`CGroup::parse`], but `None` when the controller providing the
+    /// file is not enabled.
+    fn parse_optional<T: FromStr>(&self, name: &str) -> Result<Option<T>> {
+        if !self.get_file_path(name).exists() {
+            return Ok(None);
+        }
+
+        self.parse(name).map(Some)
+    }
+}
+
+/// Reads a file of one number per line, such as `cgroup.procs`.
+pub(super) fn get_u32_list_at(path: &Path) -> Result<Vec<u32>> {
+    info!("reading {}", path.display());
+    let content = fs::read_to_string(path).map_err(|e| Error::cgroup_io(path, e))?;
+
+    content
+        .lines()
+        .map(|proc| u32::from_str(proc.trim()).map_err(|_| Error::parse(path, &content)))
+        .collect()
+}
+
+/// Sends SIGKILL to each of `procs`, ignoring those that already exited.
+pub(super) fn kill_procs(procs: Vec<u32>) -> Result<()> {
+    for pid in procs {
+        info!("sending SIGKILL to {}", pid);
+        match kill(Pid::from_raw(pid as i32), Signal::SIGKILL) {
+            Ok(()) | Err(nix::errno::Errno::ESRCH) => (),
+            Err(e) => return Err(Error::syscall("kill", format!("{}, SIGKILL", pid), e)),
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process;
+
+    use super::*;
+
+    /// A fake cgroup2 hierarchy in a temporary directory: interface files are
+    /// plain files, which writes append to.
+    struct Hierarchy(PathBuf);
+
+    impl Hierarchy {
+        fn new(name: &str) -> Self {
+            let path =
+                std::env::temp_dir().join(format!("izoli-cgroup-{}-{}", name, process::id()));
+            let _ = fs::remove_dir_all(&path);
+            fs::create_dir_all(&path).unwrap();
+            Self(path)
+        }
+
+        /// Creates the interface file `path` holding `content`.
+        fn file(&self, path: &str, content: &str) {
+            let path = self.0.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+
+        fn read(&self, path: &str) -> String {
+            fs::read_to_string(self.0.join(path)).unwrap()
+        }
+
+        /// Creates the group `path` with the files every non-root group has.
+        fn group(&self, path: &str, controllers: &str, procs: &str) {
+            self.file(&format!("{}/cgroup.type", path), "domain\n");
+            self.file(&format!("{}/cgroup.controllers", path), controllers);
+            self.file(&format!("{}/cgroup.subtree_control", path), "");
+            self.file(&format!("{}/cgroup.procs", path), procs);
+            self.file(&format!("{}/cgroup.stat", path), "nr_descendants 0\n");
+            self.file(
+                &format!("{}/cgroup.events", path),
+                "populated 0\nfrozen 0\n",
+            );
+        }
+    }
+
+    impl Drop for Hierarchy {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn apply_options_writes_each_limit() {
+        let hierarchy = Hierarchy::new("apply");
+        for name in ["cpu.max", "memory.max", "pids.max", "cpuset.cpus"] {
+            hierarchy.file(&format!("box/{}", name), "");
+        }
+        let cgroup = CGroup::open_at(&hierarchy.0, "/box").unwrap();
+
+        cgroup
+            .apply_options(&CGroupOption {
+                cpu_max: Some(CGroupLimitValue::Value("1.5".parse().unwrap())),
+                memory_max: Some(CGroupLimitValue::Value(Bytes(1 << 30))),
+                pids_max: Some(CGroupLimitValue::Max),
+                cpus: Some("0-1,3".parse().unwrap()),
+                ..Default::default()
+            })
+            .unwrap();
+
+        assert_eq!(hierarchy.read("box/cpu.max"), "150000 100000");
+        assert_eq!(hierarchy.read("box/memory.max"), "1073741824");
+        assert_eq!(hierarchy.read("box/pids.max"), "max");
+        assert_eq!(hierarchy.read("box/cpuset.cpus"), "0-1,3");
+    }
+
+    #[test]
+    fn apply_options_refuses_invalid_partitions_without_writing() {
+        let hierarchy = Hierarchy::new("partition");
+        hierarchy.file("box/cpuset.cpus.partition", "member\n");
+        let cgroup = CGroup::open_at(&hierarchy.0, "box").unwrap();
+
+        let result = cgroup.apply_options(&CGroupOption {
+            cpus_partition: Some("root invalid (no cpus)".parse().unwrap()),
+            ..Default::default()
+        });
+
+        assert!(matches!(result, Err(Error::InvalidPartition { .. })));
+        assert_eq!(hierarchy.read("box/cpuset.cpus.partition"), "member\n");
+    }
+
+    #[test]
+    fn enable_controllers_walks_down_from_the_root() {
+        let hierarchy = Hierarchy::new("enable");
+        hierarchy.file("cgroup.controllers", "cpu memory pids\n");
+        hierarchy.file("cgroup.subtree_control", "");
+        hierarchy.group("a", "cpu memory\n", "");
+        hierarchy.group("a/box", "", "");
+        let cgroup = CGroup::open_at(&hierarchy.0, "a/box").unwrap();
+
+        cgroup
+            .enable_controllers(&[Controller::Cpu, Controller::Memory], false)
+            .unwrap();
+
+        assert_eq!(hierarchy.read("cgroup.subtree_control"), "+cpu +memory");
+        assert_eq!(hierarchy.read("a/cgroup.subtree_control"), "+cpu +memory");
+    }
+
+    #[test]
+    fn enable_controllers_reports_unavailable_controllers() {
+        let hierarchy = Hierarchy::new("unavailable");
+        hierarchy.file("cgroup.controllers", "cpu memory pids\n");
+        hierarchy.file("cgroup.subtree_control", "pids\n");
+        hierarchy.group("a", "cpu memory\n", "");
+        hierarchy.group("a/box", "", "");
+        let cgroup = CGroup::open_at(&hierarchy.0, "a/box").unwrap();
+
+        match cgroup.enable_controllers(&[Controller::Pids], false) {
+            Err(Error::ControllerUnavailable { controller, cgroup }) => {
+                assert_eq!(controller, Controller::Pids);
+                assert_eq!(cgroup, PathBuf::from("a"));
+            }
+            result => panic!("unexpected {:?}", result.map(|_| ())),
+        }
+        assert_eq!(hierarchy.read("a/cgroup.subtree_control"), "");
+    }
+
+    #[test]
+    fn enable_controllers_reports_or_moves_internal_processes() {
+        let hierarchy = Hierarchy::new("internal");
+        hierarchy.file("cgroup.controllers", "cpu\n");
+        hierarchy.file("cgroup.subtree_control", "cpu\n");
+        hierarchy.group("a", "cpu\n", "42\n");
+        hierarchy.group("a/box", "", "");
+        hierarchy.file("a/leaf/cgroup.procs", "");
+        let cgroup = CGroup::open_at(&hierarchy.0, "a/box").unwrap();
+
+        match cgroup.enable_controllers(&[Controller::Cpu], false) {
+            Err(Error::InternalProcesses { cgroup }) => assert_eq!(cgroup, PathBuf::from("a")),
+            result => panic!("unexpected {:?}", result.map(|_| ())),
+        }
+        assert_eq!(hierarchy.read("a/cgroup.subtree_control"), "");
+
+        cgroup.enable_controllers(&[Controller::Cpu], true).unwrap();
+        assert_eq!(hierarchy.read("a/leaf/cgroup.procs"), "42");
+        assert_eq!(hierarchy.read("a/cgroup.subtree_control"), "+cpu");
+    }
+
+    #[test]
+    fn children_and_walk_visit_the_subtree_in_order() {
+        let hierarchy = Hierarchy::new("walk");
+        hierarchy.group("p", "", "");
+        hierarchy.group("p/b", "", "7\n");
+        hierarchy.group("p/a", "", "");
+        hierarchy.group("p/a/c", "", "");
+        hierarchy.file(
+            "p/cgroup.stat",
+            "nr_descendants 3\nnr_dying_descendants 1\n",
+        );
+        hierarchy.file("p/b/cgroup.events", "populated 1\nfrozen 0\n");
+        let cgroup = CGroup::open_at(&hierarchy.0, "p").unwrap();
+
+        let children: Vec<PathBuf> = cgroup
+            .children()
+            .unwrap()
+            .into_iter()
+            .map(|child| child.path.clone())
+            .collect();
+        assert_eq!(children, [PathBuf::from("p/a"), PathBuf::from("p/b")]);
+
+        let nodes = cgroup.walk().unwrap();
+        let visited: Vec<(&Path, usize)> = nodes
+            .iter()
+            .map(|node| (node.path.as_path(), node.depth))
+            .collect();
+        assert_eq!(
+            visited,
+            [
+                (Path::new("p"), 0),
+                (Path::new("p/a"), 1),
+                (Path::new("p/a/c"), 2),
+                (Path::new("p/b"), 1),
+            ]
+        );
+        assert_eq!(nodes[0].stat.nr_descendants, 3);
+        assert_eq!(nodes[0].stat.nr_dying_descendants, 1);
+        assert!(nodes[3].events.populated);
+        assert_eq!(nodes[3].procs, [7]);
+    }
+
+    #[test]
+    fn create_child_is_a_leaf_removed_on_drop() {
+        let hierarchy = Hierarchy::new("child");
+        hierarchy.group("p", "cpu memory\n", "");
+        hierarchy.file("p/cgroup.subtree_control", "cpu memory\n");
+        let parent = CGroup::open_at(&hierarchy.0, "p").unwrap();
+
+        let child = parent.create_child("box").unwrap();
+        assert!(child.check_status());
+        assert!(!child.get_file_path("cgroup.subtree_control").exists());
+        drop(child);
+        assert!(!hierarchy.0.join("p/box").exists());
+
+        hierarchy.group("p/kept", "", "");
+        drop(parent.create_child("kept").unwrap());
+        assert!(hierarchy.0.join("p/kept").exists());
+    }
+
+    #[test]
+    fn create_internal_passes_the_parent_controllers_down() {
+        let hierarchy = Hierarchy::new("internal-child");
+        hierarchy.group("p", "cpu memory\n", "");
+        hierarchy.file("p/cgroup.subtree_control", "cpu memory\n");
+        hierarchy.group("p/mid", "cpu memory\n", "");
+        let parent = CGroup::open_at(&hierarchy.0, "p").unwrap();
+
+        parent.create_internal("mid").unwrap();
+
+        assert_eq!(
+            hierarchy.read("p/mid/cgroup.subtree_control"),
+            "+cpu +memory"
+        );
+    }
+
+    #[test]
+    fn create_internal_skips_unknown_controllers() {
+        let hierarchy = Hierarchy::new("unknown-controller");
+        hierarchy.group("p", "cpu memory dmem\n", "");
+        hierarchy.file("p/cgroup.subtree_control", "cpu dmem memory\n");
+        hierarchy.group("p/mid", "cpu memory dmem\n", "");
+        let parent = CGroup::open_at(&hierarchy.0, "p").unwrap();
+
+        parent.create_internal("mid").unwrap();
+
+        assert_eq!(
+            hierarchy.read("p/mid/cgroup.subtree_control"),
+            "+cpu +memory"
+        );
+    }
+
+    #[test]
+    fn set_type_only_makes_groups_threaded() {
+        let hierarchy = Hierarchy::new("type");
+        hierarchy.group("p", "", "");
+        let cgroup = CGroup::open_at(&hierarchy.0, "p").unwrap();
+
+        cgroup.set_type(CGroupType::Domain).unwrap();
+        assert_eq!(hierarchy.read("p/cgroup.type"), "domain\n");
+
+        match cgroup.set_type(CGroupType::DomainThreaded) {
+            Err(Error::InvalidTypeTransition { from, to, .. }) => {
+                assert_eq!(from, CGroupType::Domain);
+                assert_eq!(to, CGroupType::DomainThreaded);
+            }
+            result => panic!("unexpected {:?}", result),
+        }
+
+        cgroup.set_type(CGroupType::Threaded).unwrap();
+        assert_eq!(hierarchy.read("p/cgroup.type"), "domain\nthreaded");
 
-        Ok(CGroupLimitValue::from_str(&value).unwrap())
+        let root = CGroup::open_at(&hierarchy.0, "").unwrap();
+        assert_eq!(root.get_type().unwrap(), CGroupType::Domain);
     }
 }
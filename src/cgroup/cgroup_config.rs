@@ -0,0 +1,517 @@
+use std::{
+    collections::BTreeMap,
+    ffi::OsString,
+    fs,
+    os::unix::ffi::{OsStrExt, OsStringExt},
+    path::{Path, PathBuf},
+};
+
+use tracing::info;
+
+use crate::error::{Error, Result};
+
+use super::{
+    backend::CGroupBackend,
+    cgroup::CGroup,
+    cgroup_v1::{CGroupV1, LegacyMounts, LegacyPaths, LEGACY_CONTROLLERS},
+    controller::Controller,
+};
+
+const DEFAULT_MOUNT_POINT: &str = "/sys/fs/cgroup";
+const DEFAULT_GROUP: &str = "izoli";
+
+/// Controllers whose presence in a legacy hierarchy means the host keeps
+/// resource control on cgroup v1.
+const LIMIT_CONTROLLERS: [&str; 4] = ["memory", "cpu", "pids", "cpuset"];
+
+/// Where izoli keeps its cgroups: the cgroup2 mount point and the group,
+/// relative to it, under which box groups are created.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CGroupConfig {
+    pub mount_point: PathBuf,
+    pub parent: PathBuf,
+    /// The hierarchy box groups are created in. `mount_point` only applies
+    /// to [`CGroupHierarchy::Unified`].
+    pub hierarchy: CGroupHierarchy,
+    /// The parent group in each legacy hierarchy, keyed by controller, where
+    /// it was detected apart from `parent`. Hierarchies not listed use
+    /// `parent`; [`CGroupConfig::set_parent`] clears it.
+    pub legacy_parents: LegacyPaths,
+    /// Move processes found in an ancestor group into a leaf group when a
+    /// controller has to be enabled there. See [`CGroup::enable_controllers`].
+    /// This moves the calling process out of its own cgroup as well, along
+    /// with anything else running beside it; it is off by default.
+    pub move_stray_processes: bool,
+}
+
+/// The kind of cgroup hierarchy boxes are placed in.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum CGroupHierarchy {
+    /// The cgroup2 hierarchy.
+    #[default]
+    Unified,
+    /// Per-controller cgroup v1 hierarchies, on legacy and hybrid hosts.
+    Legacy(LegacyMounts),
+}
+
+impl CGroupConfig {
+    pub fn new(mount_point: impl Into<PathBuf>, parent: impl Into<PathBuf>) -> Self {
+        Self {
+            mount_point: mount_point.into(),
+            parent: parent.into(),
+            hierarchy: CGroupHierarchy::Unified,
+            legacy_parents: LegacyPaths::new(),
+            move_stray_processes: false,
+        }
+    }
+
+    /// Places box groups under `parent` in every hierarchy.
+    pub fn set_parent(&mut self, parent: impl Into<PathBuf>) {
+        self.parent = parent.into();
+        self.legacy_parents.clear();
+    }
+
+    /// Detects the cgroup layout from `/proc/self/mountinfo` and places the
+    /// parent group `izoli` under the cgroup this process belongs to, so a
+    /// supervisor running inside a delegated slice or a container stays
+    /// within the subtree it was given. Hosts that mount the memory, cpu,
+    /// pids or cpuset controller in a legacy hierarchy get the v1 backend,
+    /// with the parent group placed under this process's group in each
+    /// hierarchy.
+    pub fn detect() -> Result<Self> {
+        let mountinfo = fs::read_to_string("/proc/self/mountinfo")
+            .map_err(|e| Error::io("/proc/self/mountinfo", e))?;
+        let cgroup = fs::read_to_string("/proc/self/cgroup")
+            .map_err(|e| Error::io("/proc/self/cgroup", e))?;
+
+        let legacy = Self::parse_legacy_mountinfo(&mountinfo);
+        if let Some(controller) = LIMIT_CONTROLLERS
+            .iter()
+            .find(|controller| legacy.contains_key(**controller))
+        {
+            let (mount_root, _) = &legacy[*controller];
+            let own = Self::parse_self_legacy_cgroup(&cgroup, controller)
+                .ok_or_else(|| Error::parse("/proc/self/cgroup", &cgroup))?;
+            let parent = Self::get_parent(&own, mount_root);
+            let legacy_parents = Self::get_legacy_parents(&legacy, &cgroup, &parent);
+            let mounts: LegacyMounts = legacy
+                .into_iter()
+                .map(|(controller, (_, mount_point))| (controller, mount_point))
+                .collect();
+
+            info!(
+                "detected cgroup v1 hierarchies {:?} with parent {} ({:?} elsewhere)",
+                mounts.keys().collect::<Vec<_>>(),
+                parent.display(),
+                legacy_parents
+            );
+
+            return Ok(Self {
+                hierarchy: CGroupHierarchy::Legacy(mounts),
+                legacy_parents,
+                ..Self::new(DEFAULT_MOUNT_POINT, parent)
+            });
+        }
+
+        let (mount_root, mount_point) = Self::parse_mountinfo(&mountinfo)
+            .unwrap_or_else(|| (PathBuf::from("/"), PathBuf::from(DEFAULT_MOUNT_POINT)));
+        let own = Self::parse_self_cgroup(&cgroup)
+            .ok_or_else(|| Error::parse("/proc/self/cgroup", &cgroup))?;
+        let parent = Self::get_parent(&own, &mount_root);
+
+        info!(
+            "detected cgroup2 at {} with parent {}",
+            mount_point.display(),
+            parent.display()
+        );
+
+        Ok(Self::new(mount_point, parent))
+    }
+
+    /// The mount may expose only a subtree of the hierarchy, in which case
+    /// our own path has to be made relative to the mount's root.
+    fn get_parent(own: &Path, mount_root: &Path) -> PathBuf {
+        let own = own.strip_prefix(mount_root).unwrap_or(own);
+
+        own.strip_prefix("/").unwrap_or(own).join(DEFAULT_GROUP)
+    }
+
+    /// The parent group in each legacy hierarchy whose own group, relative
+    /// to its mount, differs from the one `parent` was derived from.
+    fn get_legacy_parents(
+        legacy: &BTreeMap<String, (PathBuf, PathBuf)>,
+        cgroup: &str,
+        parent: &Path,
+    ) -> LegacyPaths {
+        legacy
+            .iter()
+            .filter_map(|(controller, (mount_root, _))| {
+                let own = Self::parse_self_legacy_cgroup(cgroup, controller)?;
+                let own_parent = Self::get_parent(&own, mount_root);
+
+                (own_parent != parent).then(|| (controller.clone(), own_parent))
+            })
+            .collect()
+    }
+
+    /// The path of the group `name` in each legacy hierarchy.
+    fn get_legacy_paths(&self, mounts: &LegacyMounts, name: &str) -> LegacyPaths {
+        mounts
+            .keys()
+            .map(|controller| {
+                let parent = self.legacy_parents.get(controller).unwrap_or(&self.parent);
+                (controller.clone(), parent.join(name))
+            })
+            .collect()
+    }
+
+    /// Encodes `parent` and `legacy_parents` for recording where a box was
+    /// created: `parent` on the first line, then one `controller:parent`
+    /// line per legacy hierarchy listed.
+    pub fn get_parent_record(&self) -> Vec<u8> {
+        let mut record = self.parent.as_os_str().as_bytes().to_vec();
+
+        for (controller, parent) in &self.legacy_parents {
+            record.push(b'\n');
+            record.extend_from_slice(controller.as_bytes());
+            record.push(b':');
+            record.extend_from_slice(parent.as_os_str().as_bytes());
+        }
+
+        record
+    }
+
+    /// Restores the parents from a record made by
+    /// [`CGroupConfig::get_parent_record`].
+    pub fn set_parent_record(&mut self, record: &[u8]) {
+        let mut lines = record.split(|&b| b == b'\n');
+        self.set_parent(OsString::from_vec(
+            lines.next().unwrap_or_default().to_vec(),
+        ));
+
+        for line in lines {
+            if let Some(colon) = line.iter().position(|&b| b == b':') {
+                self.legacy_parents.insert(
+                    String::from_utf8_lossy(&line[..colon]).into_owned(),
+                    OsString::from_vec(line[colon + 1..].to_vec()).into(),
+                );
+            }
+        }
+    }
+
+    /// Detects the cgroup2 mount point from `/proc/self/mountinfo`, falling
+    /// back to `/sys/fs/cgroup` when none is listed.
+    pub fn detect_mount_point() -> Result<PathBuf> {
+        Ok(Self::find_mount()?.1)
+    }
+
+    /// Returns the cgroup2 mount point listed in `/proc/self/mountinfo`, if
+    /// there is one.
+    pub fn find_mount_point() -> Result<Option<PathBuf>> {
+        Ok(Self::read_mountinfo()?.map(|(_, mount_point)| mount_point))
+    }
+
+    fn find_mount() -> Result<(PathBuf, PathBuf)> {
+        Ok(Self::read_mountinfo()?
+            .unwrap_or_else(|| (PathBuf::from("/"), PathBuf::from(DEFAULT_MOUNT_POINT))))
+    }
+
+    fn read_mountinfo() -> Result<Option<(PathBuf, PathBuf)>> {
+        let mountinfo = fs::read_to_string("/proc/self/mountinfo")
+            .map_err(|e| Error::io("/proc/self/mountinfo", e))?;
+
+        Ok(Self::parse_mountinfo(&mountinfo))
+    }
+
+    /// Creates (if needed) the group `name` under the parent group.
+    pub fn create(&self, name: &str) -> Result<CGroup> {
+        CGroup::new_at(&self.mount_point, self.parent.join(name))
+    }
+
+    /// Creates (if needed) the group `name` under the parent group and makes
+    /// `controllers` available in it.
+    pub fn create_with_controllers(
+        &self,
+        name: &str,
+        controllers: &[Controller],
+    ) -> Result<CGroup> {
+        let cgroup = self.create(name)?;
+        cgroup.enable_controllers(controllers, self.move_stray_processes)?;
+
+        Ok(cgroup)
+    }
+
+    /// Opens the existing group `name` under the parent group.
+    pub fn open(&self, name: &str) -> Result<CGroup> {
+        CGroup::open_at(&self.mount_point, self.parent.join(name))
+    }
+
+    /// Creates (if needed) the group `name` under the parent group in the
+    /// configured hierarchy, with `controllers` available in it.
+    pub fn create_backend(
+        &self,
+        name: &str,
+        controllers: &[Controller],
+    ) -> Result<Box<dyn CGroupBackend>> {
+        match &self.hierarchy {
+            CGroupHierarchy::Unified => {
+                Ok(Box::new(self.create_with_controllers(name, controllers)?))
+            }
+            CGroupHierarchy::Legacy(mounts) => Ok(Box::new(CGroupV1::new(
+                mounts.clone(),
+                self.get_legacy_paths(mounts, name),
+                controllers,
+            )?)),
+        }
+    }
+
+    /// Opens the existing group `name` under the parent group in the
+    /// configured hierarchy.
+    pub fn open_backend(&self, name: &str) -> Result<Box<dyn CGroupBackend>> {
+        match &self.hierarchy {
+            CGroupHierarchy::Unified => Ok(Box::new(self.open(name)?)),
+            CGroupHierarchy::Legacy(mounts) => Ok(Box::new(CGroupV1::open(
+                mounts.clone(),
+                self.get_legacy_paths(mounts, name),
+            )?)),
+        }
+    }
+
+    /// Creates (if needed) the parent group itself.
+    pub fn create_parent(&self) -> Result<CGroup> {
+        CGroup::new_at(&self.mount_point, &self.parent)
+    }
+
+    /// Returns the root and mount point of the first cgroup2 mount.
+    fn parse_mountinfo(mountinfo: &str) -> Option<(PathBuf, PathBuf)> {
+        mountinfo.lines().find_map(|line| {
+            let (fields, fs_fields) = line.split_once(" - ")?;
+            if fs_fields.split_whitespace().next()? != "cgroup2" {
+                return None;
+            }
+
+            let mut fields = fields.split_whitespace().skip(3);
+            let root = fields.next()?;
+            let mount_point = fields.next()?;
+
+            Some((unescape(root), unescape(mount_point)))
+        })
+    }
+
+    /// Returns the root and mount point of each legacy hierarchy holding a
+    /// controller izoli uses, keyed by controller.
+    fn parse_legacy_mountinfo(mountinfo: &str) -> BTreeMap<String, (PathBuf, PathBuf)> {
+        let mut mounts = BTreeMap::new();
+
+        for line in mountinfo.lines() {
+            let Some((fields, fs_fields)) = line.split_once(" - ") else {
+                continue;
+            };
+            let mut fs_fields = fs_fields.split_whitespace();
+            if fs_fields.next() != Some("cgroup") {
+                continue;
+            }
+            let Some(options) = fs_fields.nth(1) else {
+                continue;
+            };
+
+            let mut fields = fields.split_whitespace().skip(3);
+            let (Some(root), Some(mount_point)) = (fields.next(), fields.next()) else {
+                continue;
+            };
+
+            for controller in options
+                .split(',')
+                .filter(|option| LEGACY_CONTROLLERS.contains(option))
+            {
+                mounts
+                    .entry(controller.to_string())
+                    .or_insert_with(|| (unescape(root), unescape(mount_point)));
+            }
+        }
+
+        mounts
+    }
+
+    /// Returns the path in the legacy hierarchy of `controller` from
+    /// `/proc/self/cgroup`.
+    fn parse_self_legacy_cgroup(cgroup: &str, controller: &str) -> Option<PathBuf> {
+        cgroup.lines().find_map(|line| {
+            let mut fields = line.splitn(3, ':').skip(1);
+            let controllers = fields.next()?;
+            let path = fields.next()?;
+
+            controllers
+                .split(',')
+                .any(|c| c == controller)
+                .then(|| PathBuf::from(path.trim()))
+        })
+    }
+
+    /// Returns the unified hierarchy path from `/proc/self/cgroup`.
+    fn parse_self_cgroup(cgroup: &str) -> Option<PathBuf> {
+        cgroup
+            .lines()
+            .find_map(|line| line.strip_prefix("0::"))
+            .map(|path| PathBuf::from(path.trim()))
+    }
+}
+
+impl Default for CGroupConfig {
+    fn default() -> Self {
+        Self::new(DEFAULT_MOUNT_POINT, DEFAULT_GROUP)
+    }
+}
+
+/// Decodes the octal escapes (`\040` for a space, ...) used in mountinfo.
+fn unescape(field: &str) -> PathBuf {
+    let bytes = field.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] == b'\\' && i + 4 <= bytes.len() {
+            let digits = std::str::from_utf8(&bytes[i + 1..i + 4]).unwrap_or_default();
+            if let Ok(c) = u8::from_str_radix(digits, 8) {
+                out.push(c);
+                i += 4;
+                continue;
+            }
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+
+    PathBuf::from(OsString::from_vec(out))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MOUNTINFO: &str = "\
+22 1 0:21 / /proc rw,nosuid,nodev,noexec,relatime shared:5 - proc proc rw
+30 25 0:26 / /sys/fs/cgroup/memory rw,nosuid shared:12 - cgroup cgroup rw,memory
+31 25 0:27 /lxc/c1 /sys/fs/cgroup/cpu,cpuacct rw master:1 - cgroup cgroup rw,cpu,cpuacct
+32 25 0:28 / /sys/fs/cgroup/net_cls rw - cgroup cgroup rw,net_cls
+33 25 0:29 /user.slice /mnt/my\\040cgroup rw shared:9 master:2 - cgroup2 cgroup2 rw,nsdelegate
+34 25 0:30 / /sys/fs/cgroup/unified rw - cgroup2 cgroup2 rw
+";
+
+    #[test]
+    fn parse_mountinfo_finds_first_cgroup2_mount() {
+        assert_eq!(
+            CGroupConfig::parse_mountinfo(MOUNTINFO),
+            Some((
+                PathBuf::from("/user.slice"),
+                PathBuf::from("/mnt/my cgroup")
+            ))
+        );
+        assert_eq!(
+            CGroupConfig::parse_mountinfo(
+                MOUNTINFO
+                    .lines()
+                    .take(4)
+                    .collect::<Vec<_>>()
+                    .join("\n")
+                    .as_str()
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn parse_legacy_mountinfo_keys_hierarchies_by_controller() {
+        let mounts = CGroupConfig::parse_legacy_mountinfo(MOUNTINFO);
+
+        assert_eq!(
+            mounts.get("memory"),
+            Some(&(PathBuf::from("/"), PathBuf::from("/sys/fs/cgroup/memory")))
+        );
+        assert_eq!(
+            mounts.get("cpu"),
+            Some(&(
+                PathBuf::from("/lxc/c1"),
+                PathBuf::from("/sys/fs/cgroup/cpu,cpuacct")
+            ))
+        );
+        assert_eq!(mounts.get("cpu"), mounts.get("cpuacct"));
+        assert!(!mounts.contains_key("net_cls"));
+        assert!(!mounts.contains_key("rw"));
+    }
+
+    #[test]
+    fn parse_self_cgroup_reads_unified_and_legacy_paths() {
+        let cgroup = "12:memory:/user.slice/a\n11:cpu,cpuacct:/b\n0::/user.slice/c.scope\n";
+
+        assert_eq!(
+            CGroupConfig::parse_self_cgroup(cgroup),
+            Some(PathBuf::from("/user.slice/c.scope"))
+        );
+        assert_eq!(
+            CGroupConfig::parse_self_legacy_cgroup(cgroup, "cpuacct"),
+            Some(PathBuf::from("/b"))
+        );
+        assert_eq!(CGroupConfig::parse_self_legacy_cgroup(cgroup, "pids"), None);
+        assert_eq!(CGroupConfig::parse_self_cgroup("1:name=systemd:/\n"), None);
+    }
+
+    #[test]
+    fn get_parent_is_relative_to_mount_root() {
+        assert_eq!(
+            CGroupConfig::get_parent(Path::new("/user.slice/c.scope"), Path::new("/")),
+            PathBuf::from("user.slice/c.scope/izoli")
+        );
+        assert_eq!(
+            CGroupConfig::get_parent(Path::new("/user.slice/c.scope"), Path::new("/user.slice")),
+            PathBuf::from("c.scope/izoli")
+        );
+        assert_eq!(
+            CGroupConfig::get_parent(Path::new("/"), Path::new("/")),
+            PathBuf::from("izoli")
+        );
+    }
+
+    #[test]
+    fn legacy_parents_follow_each_hierarchy() {
+        let legacy = CGroupConfig::parse_legacy_mountinfo(MOUNTINFO);
+        let cgroup = "4:memory:/user.slice/a.scope\n3:cpu,cpuacct:/lxc/c1/b\n";
+        let parent = CGroupConfig::get_parent(Path::new("/user.slice/a.scope"), Path::new("/"));
+        let mut config = CGroupConfig {
+            legacy_parents: CGroupConfig::get_legacy_parents(&legacy, cgroup, &parent),
+            ..CGroupConfig::new(DEFAULT_MOUNT_POINT, parent)
+        };
+
+        let mounts: LegacyMounts = legacy
+            .into_iter()
+            .map(|(controller, (_, mount_point))| (controller, mount_point))
+            .collect();
+        let paths = config.get_legacy_paths(&mounts, "box_1");
+        assert_eq!(
+            paths.get("memory"),
+            Some(&PathBuf::from("user.slice/a.scope/izoli/box_1"))
+        );
+        assert_eq!(paths.get("cpu"), Some(&PathBuf::from("b/izoli/box_1")));
+        assert_eq!(paths.get("cpu"), paths.get("cpuacct"));
+
+        let record = config.get_parent_record();
+        let mut restored = CGroupConfig::default();
+        restored.set_parent_record(&record);
+        assert_eq!(restored.parent, config.parent);
+        assert_eq!(restored.legacy_parents, config.legacy_parents);
+
+        config.set_parent("izoli");
+        assert_eq!(
+            config.get_legacy_paths(&mounts, "box_1").get("cpu"),
+            Some(&PathBuf::from("izoli/box_1"))
+        );
+    }
+
+    #[test]
+    fn unescape_decodes_octal_escapes() {
+        assert_eq!(unescape("/a\\040b\\011c"), PathBuf::from("/a b\tc"));
+        assert_eq!(unescape("/back\\134slash"), PathBuf::from("/back\\slash"));
+        assert_eq!(unescape("/short\\04"), PathBuf::from("/short\\04"));
+        assert_eq!(unescape("/not\\xyzoctal"), PathBuf::from("/not\\xyzoctal"));
+    }
+}
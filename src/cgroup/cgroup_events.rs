@@ -0,0 +1,88 @@
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CGroupEvents {
+    pub populated: bool,
+    pub frozen: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParseCGroupEventsError;
+
+impl FromStr for CGroupEvents {
+    type Err = ParseCGroupEventsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut events = Self {
+            populated: false,
+            frozen: false,
+        };
+
+        for line in s.lines() {
+            let mut spl = line.split_whitespace();
+            let (Some(key), Some(value)) = (spl.next(), spl.next()) else {
+                continue;
+            };
+
+            let value = match value {
+                "0" => false,
+                "1" => true,
+                _ => return Err(ParseCGroupEventsError),
+            };
+
+            match key {
+                "populated" => events.populated = value,
+                "frozen" => events.frozen = value,
+                _ => (),
+            }
+        }
+
+        Ok(events)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_populated_and_frozen() {
+        assert_eq!(
+            "populated 1\nfrozen 0\n".parse(),
+            Ok(CGroupEvents {
+                populated: true,
+                frozen: false
+            })
+        );
+        assert_eq!(
+            "populated 0\nfrozen 1\n".parse(),
+            Ok(CGroupEvents {
+                populated: false,
+                frozen: true
+            })
+        );
+    }
+
+    #[test]
+    fn ignores_unknown_keys_and_short_lines() {
+        assert_eq!(
+            "populated 1\nkilled 0\nfrozen\n\n".parse(),
+            Ok(CGroupEvents {
+                populated: true,
+                frozen: false
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_values_other_than_0_and_1() {
+        for s in ["populated 2\n", "frozen yes\n", "killed -1\n"] {
+            assert_eq!(
+                s.parse::<CGroupEvents>(),
+                Err(ParseCGroupEventsError),
+                "{}",
+                s
+            );
+        }
+    }
+}
@@ -0,0 +1,15 @@
+use std::path::PathBuf;
+
+use super::{cgroup_events::CGroupEvents, cgroup_stat::CGroupStat};
+
+/// A cgroup visited by [`CGroup::walk`](super::cgroup::CGroup::walk).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CGroupNode {
+    /// Path relative to the cgroup2 mount point.
+    pub path: PathBuf,
+    /// Distance from the group the walk started at.
+    pub depth: usize,
+    pub stat: CGroupStat,
+    pub events: CGroupEvents,
+    pub procs: Vec<u32>,
+}
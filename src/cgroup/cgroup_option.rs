@@ -1,9 +1,69 @@
-use super::{cpu_limit::CpuLimit, limit_value::CGroupLimitValue};
+use std::collections::BTreeMap;
+
+use super::{
+    controller::Controller,
+    cpu_limit::CpuQuota,
+    cpuset::{CpuSet, CpusetPartition},
+    hugetlb::HugePageSize,
+    limit_value::CGroupLimitValue,
+    rdma::RdmaLimit,
+    units::{Bytes, TimeSpan},
+};
 
 #[derive(Debug, Clone, Default)]
 pub struct CGroupOption {
-    pub cpu_max: Option<CpuLimit>,
-    pub cpus: Option<Vec<u32>>,
-    pub memory_max: Option<CGroupLimitValue<u32>>,
+    /// CPU time as a number of CPUs, e.g. `1.5`, enforced over `cpu_period`.
+    pub cpu_max: Option<CGroupLimitValue<CpuQuota>>,
+    /// Defaults to [`DEFAULT_CPU_PERIOD`](super::cpu_limit::DEFAULT_CPU_PERIOD).
+    pub cpu_period: Option<TimeSpan>,
+    pub cpus: Option<CpuSet>,
+    /// CPUs no sibling box may claim, see `cpuset.cpus.exclusive`.
+    pub cpus_exclusive: Option<CpuSet>,
+    /// Makes the box a cpuset partition root owning its exclusive CPUs.
+    pub cpus_partition: Option<CpusetPartition>,
+    pub memory_max: Option<CGroupLimitValue<Bytes>>,
     pub pids_max: Option<CGroupLimitValue<u32>>,
+    /// `hugetlb.<size>.max` for each huge page size.
+    pub hugetlb_max: Option<BTreeMap<HugePageSize, CGroupLimitValue<Bytes>>>,
+    /// `misc.max` for each misc resource, e.g. `sev` or `sev_es`.
+    pub misc_max: Option<BTreeMap<String, CGroupLimitValue<u64>>>,
+    /// `rdma.max` for each RDMA device.
+    pub rdma_max: Option<Vec<RdmaLimit>>,
+}
+
+impl CGroupOption {
+    /// Controllers that must be enabled for the group to apply these options.
+    pub fn get_required_controllers(&self) -> Vec<Controller> {
+        let mut controllers = Vec::new();
+
+        if self.cpu_max.is_some() || self.cpu_period.is_some() {
+            controllers.push(Controller::Cpu);
+        }
+
+        if self.cpus.is_some() || self.cpus_exclusive.is_some() || self.cpus_partition.is_some() {
+            controllers.push(Controller::Cpuset);
+        }
+
+        if self.memory_max.is_some() {
+            controllers.push(Controller::Memory);
+        }
+
+        if self.pids_max.is_some() {
+            controllers.push(Controller::Pids);
+        }
+
+        if self.hugetlb_max.is_some() {
+            controllers.push(Controller::Hugetlb);
+        }
+
+        if self.misc_max.is_some() {
+            controllers.push(Controller::Misc);
+        }
+
+        if self.rdma_max.is_some() {
+            controllers.push(Controller::Rdma);
+        }
+
+        controllers
+    }
 }
@@ -18,14 +18,59 @@ impl FromStr for CGroupStat {
             nr_dying_descendants: 0,
         };
 
-        s.lines()
-            .map(|l| l.trim().split(" ").collect())
-            .for_each(|s: Vec<&str>| match &*s[0] {
-                "nr_descendants" => stat.nr_descendants = u64::from_str(s[1]).unwrap(),
-                "nr_dying_descendants" => stat.nr_dying_descendants = u64::from_str(s[1]).unwrap(),
+        for line in s.lines() {
+            let mut spl = line.split_whitespace();
+            let (Some(key), Some(value)) = (spl.next(), spl.next()) else {
+                continue;
+            };
+
+            let value = u64::from_str(value).map_err(|_| ParseCGroupStatError)?;
+
+            match key {
+                "nr_descendants" => stat.nr_descendants = value,
+                "nr_dying_descendants" => stat.nr_dying_descendants = value,
                 _ => (),
-            });
+            }
+        }
 
         Ok(stat)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_descendant_counts() {
+        assert_eq!(
+            "nr_descendants 3\nnr_dying_descendants 1\n".parse(),
+            Ok(CGroupStat {
+                nr_descendants: 3,
+                nr_dying_descendants: 1
+            })
+        );
+    }
+
+    #[test]
+    fn ignores_unknown_keys_and_short_lines() {
+        assert_eq!(
+            "nr_descendants 2\nnr_subsys_cpu 4\nnr_dying_descendants\n\n".parse(),
+            Ok(CGroupStat {
+                nr_descendants: 2,
+                nr_dying_descendants: 0
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_values_that_are_not_counts() {
+        for s in [
+            "nr_descendants x\n",
+            "nr_dying_descendants -1\n",
+            "nr_subsys_cpu 1.5\n",
+        ] {
+            assert_eq!(s.parse::<CGroupStat>(), Err(ParseCGroupStatError), "{}", s);
+        }
+    }
+}
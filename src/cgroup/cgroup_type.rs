@@ -0,0 +1,67 @@
+use std::{fmt, str::FromStr};
+
+/// The type of a cgroup, as found in `cgroup.type`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CGroupType {
+    /// A normal cgroup, or the root of a domain subtree.
+    Domain,
+    /// A domain cgroup serving as the root of a threaded subtree.
+    DomainThreaded,
+    /// A cgroup in an invalid state, which cannot be populated or have
+    /// controllers enabled until it is made threaded.
+    DomainInvalid,
+    /// A member of a threaded subtree.
+    Threaded,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct ParseCGroupTypeError;
+
+impl FromStr for CGroupType {
+    type Err = ParseCGroupTypeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "domain" => Ok(Self::Domain),
+            "domain threaded" => Ok(Self::DomainThreaded),
+            "domain invalid" => Ok(Self::DomainInvalid),
+            "threaded" => Ok(Self::Threaded),
+            _ => Err(ParseCGroupTypeError),
+        }
+    }
+}
+
+impl fmt::Display for CGroupType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CGroupType::Domain => write!(f, "domain"),
+            CGroupType::DomainThreaded => write!(f, "domain threaded"),
+            CGroupType::DomainInvalid => write!(f, "domain invalid"),
+            CGroupType::Threaded => write!(f, "threaded"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips() {
+        for ty in [
+            CGroupType::Domain,
+            CGroupType::DomainThreaded,
+            CGroupType::DomainInvalid,
+            CGroupType::Threaded,
+        ] {
+            assert_eq!(format!("{}\n", ty).parse(), Ok(ty));
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_types() {
+        for s in ["", "domain  threaded", "Domain", "threaded domain"] {
+            assert_eq!(s.parse::<CGroupType>(), Err(ParseCGroupTypeError), "{}", s);
+        }
+    }
+}
@@ -0,0 +1,12 @@
+use super::units::{Bytes, TimeSpan};
+
+/// Resources used by a group, from whichever controllers are available to it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CGroupUsage {
+    /// CPU time consumed by every task in the group.
+    pub cpu_usage: Option<TimeSpan>,
+    /// Memory currently charged to the group.
+    pub memory_current: Option<Bytes>,
+    /// Number of tasks in the group.
+    pub pids_current: Option<u64>,
+}
@@ -0,0 +1,723 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs::{self, File},
+    io::Write,
+    os::fd::OwnedFd,
+    path::{Path, PathBuf},
+    str::FromStr,
+    thread,
+    time::{Duration, Instant},
+};
+
+use tracing::info;
+
+use crate::error::{Error, Result};
+
+use super::{
+    backend::CGroupBackend,
+    cgroup::{self, CGroup, STATE_TIMEOUT},
+    cgroup_option::CGroupOption,
+    cgroup_usage::CGroupUsage,
+    controller::Controller,
+    cpu_limit::{CpuLimit, DEFAULT_CPU_PERIOD},
+    cpuset::CpuSet,
+    limit_value::CGroupLimitValue,
+    units::{Bytes, TimeSpan},
+};
+
+/// How often the freezer state and the group's processes are checked; v1
+/// has no `cgroup.events` to wait on.
+const STATE_POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Controllers whose legacy hierarchies izoli places boxes in.
+pub(super) const LEGACY_CONTROLLERS: [&str; 9] = [
+    "cpu", "cpuacct", "cpuset", "memory", "pids", "freezer", "hugetlb", "rdma", "misc",
+];
+
+/// `memory.limit_in_bytes` reads back as `LONG_MAX` rounded down to the page
+/// size when there is no limit.
+const MEMORY_UNLIMITED: u64 = i64::MAX as u64 & !0xffff;
+
+/// Mount points of the legacy hierarchies, keyed by controller. Controllers
+/// mounted together, like `cpu` and `cpuacct`, share a mount point.
+pub type LegacyMounts = BTreeMap<String, PathBuf>;
+
+/// Paths of a group relative to the root of each legacy hierarchy, keyed by
+/// controller like [`LegacyMounts`]. A process can belong to a different
+/// group in each hierarchy, so the groups placed under it differ as well.
+pub type LegacyPaths = BTreeMap<String, PathBuf>;
+
+/// A group in the legacy cgroup v1 hierarchies: one directory in each
+/// hierarchy, with limits spread over the per-controller files.
+pub struct CGroupV1 {
+    pub mounts: LegacyMounts,
+    pub paths: LegacyPaths,
+    remove_on_drop: bool,
+}
+
+impl Drop for CGroupV1 {
+    fn drop(&mut self) {
+        if !self.remove_on_drop {
+            return;
+        }
+
+        for dir in self.get_dirs() {
+            let _ = fs::remove_dir(dir);
+        }
+    }
+}
+
+impl CGroupV1 {
+    /// Creates (if needed) the group at `paths` in every hierarchy, after
+    /// checking that `controllers` are mounted. The returned handle removes
+    /// the group when dropped only if it was created by this call.
+    pub fn new(
+        mounts: LegacyMounts,
+        paths: LegacyPaths,
+        controllers: &[Controller],
+    ) -> Result<Self> {
+        let mut cgroup = Self {
+            mounts,
+            paths: Self::relative(paths),
+            remove_on_drop: false,
+        };
+
+        if let Some(controller) = controllers
+            .iter()
+            .find(|controller| !cgroup.mounts.contains_key(&controller.to_string()))
+        {
+            return Err(Error::ControllerUnavailable {
+                controller: *controller,
+                cgroup: cgroup.get_path().to_path_buf(),
+            });
+        }
+
+        for dir in cgroup.get_dirs() {
+            if !dir.is_dir() {
+                info!("creating cgroup {}", dir.display());
+                fs::create_dir_all(&dir).map_err(|e| Error::cgroup_io(&dir, e))?;
+                cgroup.remove_on_drop = true;
+            }
+        }
+        cgroup.init_cpuset()?;
+
+        Ok(cgroup)
+    }
+
+    /// Opens an existing group without creating it. The directories are left
+    /// in place when the returned handle is dropped.
+    pub fn open(mounts: LegacyMounts, paths: LegacyPaths) -> Result<Self> {
+        let cgroup = Self {
+            mounts,
+            paths: Self::relative(paths),
+            remove_on_drop: false,
+        };
+
+        if !cgroup.get_dirs().iter().any(|dir| dir.is_dir()) {
+            return Err(Error::cgroup_io(
+                cgroup.get_path(),
+                std::io::ErrorKind::NotFound.into(),
+            ));
+        }
+
+        Ok(cgroup)
+    }
+
+    fn relative(paths: LegacyPaths) -> LegacyPaths {
+        paths
+            .into_iter()
+            .map(|(controller, path)| {
+                let path = path.strip_prefix("/").unwrap_or(&path).to_path_buf();
+                (controller, path)
+            })
+            .collect()
+    }
+
+    /// The directory of the group in each hierarchy.
+    pub fn get_dirs(&self) -> Vec<PathBuf> {
+        self.mounts
+            .iter()
+            .filter_map(|(controller, mount_point)| {
+                Some(mount_point.join(self.paths.get(controller)?))
+            })
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect()
+    }
+
+    /// The directory of the group in the hierarchy of `controller`.
+    pub fn get_dir(&self, controller: &'static str) -> Result<PathBuf> {
+        match (self.mounts.get(controller), self.paths.get(controller)) {
+            (Some(mount_point), Some(path)) => Ok(mount_point.join(path)),
+            _ => Err(Error::Unsupported {
+                cgroup: self.get_path().to_path_buf(),
+                feature: controller,
+            }),
+        }
+    }
+
+    /// New cpuset groups start without CPUs or memory nodes and refuse tasks
+    /// until given some, so each level of the path inherits its parent's.
+    fn init_cpuset(&self) -> Result<()> {
+        let (Some(mut parent), Some(path)) =
+            (self.mounts.get("cpuset").cloned(), self.paths.get("cpuset"))
+        else {
+            return Ok(());
+        };
+
+        for component in path.components() {
+            let dir = parent.join(component);
+            for name in ["cpuset.cpus", "cpuset.mems"] {
+                if read_file(&dir.join(name))?.trim().is_empty() {
+                    let value = read_file(&parent.join(name))?;
+                    write_file(&dir.join(name), value.trim())?;
+                }
+            }
+            parent = dir;
+        }
+
+        Ok(())
+    }
+
+    pub fn read(&self, controller: &'static str, name: &str) -> Result<String> {
+        read_file(&self.get_dir(controller)?.join(name))
+    }
+
+    pub fn write(&self, controller: &'static str, name: &str, data: &str) -> Result<()> {
+        write_file(&self.get_dir(controller)?.join(name), data)
+    }
+
+    fn parse<T: FromStr>(&self, controller: &'static str, name: &str) -> Result<T> {
+        let path = self.get_dir(controller)?.join(name);
+        let content = read_file(&path)?;
+
+        T::from_str(content.trim()).map_err(|_| Error::parse(path, &content))
+    }
+
+    /// Like [`CGroupV1::parse`], but `None` when the controller is not
+    /// mounted.
+    fn parse_optional<T: FromStr>(
+        &self,
+        controller: &'static str,
+        name: &str,
+    ) -> Result<Option<T>> {
+        if !self.mounts.contains_key(controller) {
+            return Ok(None);
+        }
+
+        self.parse(controller, name).map(Some)
+    }
+
+    /// Limit files take `-1` for no limit where cgroup2 takes `max`.
+    fn limit_to_string<T>(limit: CGroupLimitValue<T>) -> String
+    where
+        T: FromStr + std::fmt::Display,
+    {
+        match limit {
+            CGroupLimitValue::Max => "-1".to_string(),
+            CGroupLimitValue::Value(value) => value.to_string(),
+        }
+    }
+
+    /// Every process in the group and its descendants, in any hierarchy.
+    fn get_all_procs(&self) -> Result<Vec<u32>> {
+        let mut procs = Vec::new();
+
+        for dir in self.get_dirs().into_iter().filter(|dir| dir.is_dir()) {
+            for path in CGroup::get_descendant_dirs(dir)? {
+                procs.extend(cgroup::get_u32_list_at(&path.join("cgroup.procs"))?);
+            }
+        }
+        procs.sort_unstable();
+        procs.dedup();
+
+        Ok(procs)
+    }
+
+    fn wait_freezer_state(&self, state: &'static str) -> Result<()> {
+        self.wait_until(state, || {
+            Ok(self.read("freezer", "freezer.state")?.trim() == state)
+        })
+    }
+
+    /// Polls `done` until it holds, failing once [`STATE_TIMEOUT`] has
+    /// passed.
+    fn wait_until(&self, state: &'static str, done: impl Fn() -> Result<bool>) -> Result<()> {
+        let deadline = Instant::now() + STATE_TIMEOUT;
+
+        while !done()? {
+            if Instant::now() >= deadline {
+                return Err(Error::Timeout {
+                    cgroup: self.get_path().to_path_buf(),
+                    state,
+                });
+            }
+            thread::sleep(STATE_POLL_INTERVAL);
+        }
+
+        Ok(())
+    }
+}
+
+impl CGroupBackend for CGroupV1 {
+    /// The path in the first hierarchy; the others may differ in where the
+    /// supervisor's own group lies.
+    fn get_path(&self) -> &Path {
+        self.paths
+            .values()
+            .next()
+            .map_or(Path::new(""), PathBuf::as_path)
+    }
+
+    fn apply_options(&self, option: &CGroupOption) -> Result<()> {
+        info!("applying cgroup v1 options");
+        // Exclusive CPUs and partitions only exist in cgroup2.
+        if option.cpus_exclusive.is_some() {
+            return Err(Error::Unsupported {
+                cgroup: self.get_path().to_path_buf(),
+                feature: "cpuset.cpus.exclusive",
+            });
+        }
+
+        if option.cpus_partition.is_some() {
+            return Err(Error::Unsupported {
+                cgroup: self.get_path().to_path_buf(),
+                feature: "cpuset.cpus.partition",
+            });
+        }
+
+        if option.cpu_max.is_some() || option.cpu_period.is_some() {
+            info!("setting cpu.cfs_quota_us");
+            let cpu_limit = CpuLimit::from_quota(
+                option.cpu_max.unwrap_or(CGroupLimitValue::Max),
+                option.cpu_period.unwrap_or(DEFAULT_CPU_PERIOD),
+            );
+            self.write(
+                "cpu",
+                "cpu.cfs_period_us",
+                &cpu_limit.period.as_micros().to_string(),
+            )?;
+            self.write(
+                "cpu",
+                "cpu.cfs_quota_us",
+                &Self::limit_to_string(cpu_limit.max.map(TimeSpan::as_micros)),
+            )?;
+        }
+
+        if let Some(memory_max) = option.memory_max {
+            info!("setting memory.limit_in_bytes");
+            self.write(
+                "memory",
+                "memory.limit_in_bytes",
+                &Self::limit_to_string(memory_max.map(Bytes::as_u64)),
+            )?;
+        }
+
+        if let Some(pids_max) = &option.pids_max {
+            info!("setting pids.max");
+            self.write("pids", "pids.max", &pids_max.to_string())?;
+        }
+
+        if let Some(cpus) = &option.cpus {
+            info!("setting cpuset.cpus");
+            self.write("cpuset", "cpuset.cpus", &cpus.to_string())?;
+        }
+
+        for (size, hugetlb_limit) in option.hugetlb_max.iter().flatten() {
+            info!("setting hugetlb.{}.limit_in_bytes", size);
+            self.write(
+                "hugetlb",
+                &format!("hugetlb.{}.limit_in_bytes", size),
+                &Self::limit_to_string(hugetlb_limit.map(Bytes::as_u64)),
+            )?;
+        }
+
+        for (resource, misc_limit) in option.misc_max.iter().flatten() {
+            info!("setting misc.max for {}", resource);
+            self.write("misc", "misc.max", &format!("{} {}", resource, misc_limit))?;
+        }
+
+        for rdma_limit in option.rdma_max.iter().flatten() {
+            info!("setting rdma.max for {}", rdma_limit.device);
+            self.write("rdma", "rdma.max", &rdma_limit.to_string())?;
+        }
+
+        Ok(())
+    }
+
+    /// Legacy hierarchies cannot be targeted by `CLONE_INTO_CGROUP`.
+    fn open_fd(&self) -> Result<Option<OwnedFd>> {
+        Ok(None)
+    }
+
+    fn add_procs(&self, procs: Vec<u32>) -> Result<()> {
+        for dir in self.get_dirs() {
+            for pid in &procs {
+                write_file(&dir.join("cgroup.procs"), &pid.to_string())?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn get_procs(&self) -> Result<Vec<u32>> {
+        match self.get_dirs().first() {
+            Some(dir) => cgroup::get_u32_list_at(&dir.join("cgroup.procs")),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    fn get_cpu_max(&self) -> Result<CpuLimit> {
+        let quota: i64 = self.parse("cpu", "cpu.cfs_quota_us")?;
+        let period: u64 = self.parse("cpu", "cpu.cfs_period_us")?;
+
+        Ok(CpuLimit {
+            max: match u64::try_from(quota) {
+                Ok(quota) => CGroupLimitValue::Value(TimeSpan::from_micros(quota)),
+                Err(_) => CGroupLimitValue::Max,
+            },
+            period: TimeSpan::from_micros(period),
+        })
+    }
+
+    fn get_memory_max(&self) -> Result<CGroupLimitValue<Bytes>> {
+        let limit: u64 = self.parse("memory", "memory.limit_in_bytes")?;
+
+        if limit >= MEMORY_UNLIMITED {
+            Ok(CGroupLimitValue::Max)
+        } else {
+            Ok(CGroupLimitValue::Value(Bytes(limit)))
+        }
+    }
+
+    fn get_pids_max(&self) -> Result<CGroupLimitValue<u32>> {
+        self.parse("pids", "pids.max")
+    }
+
+    fn get_cpuset_cpus(&self) -> Result<CpuSet> {
+        self.parse("cpuset", "cpuset.cpus")
+    }
+
+    fn get_usage(&self) -> Result<CGroupUsage> {
+        let cpu_usage: Option<u64> = self.parse_optional("cpuacct", "cpuacct.usage")?;
+
+        Ok(CGroupUsage {
+            cpu_usage: cpu_usage.map(|nanos| TimeSpan(Duration::from_nanos(nanos))),
+            memory_current: self.parse_optional("memory", "memory.usage_in_bytes")?,
+            pids_current: self.parse_optional("pids", "pids.current")?,
+        })
+    }
+
+    fn freeze(&self) -> Result<()> {
+        info!("freezing cgroup");
+        self.write("freezer", "freezer.state", "FROZEN")?;
+        self.wait_freezer_state("FROZEN")
+    }
+
+    fn thaw(&self) -> Result<()> {
+        info!("thawing cgroup");
+        self.write("freezer", "freezer.state", "THAWED")?;
+        self.wait_freezer_state("THAWED")
+    }
+
+    /// Signals every process in the group, frozen first when the freezer
+    /// hierarchy is mounted so forks cannot escape, and waits for the group
+    /// to empty. Processes still found while waiting, such as ones forked
+    /// past an unfrozen group, are signalled again.
+    fn kill_all(&self) -> Result<()> {
+        info!("killing all processes in cgroup");
+        let freezer = self.mounts.contains_key("freezer");
+
+        if freezer {
+            self.freeze()?;
+        }
+        let result = self.get_all_procs().and_then(cgroup::kill_procs);
+        if freezer {
+            self.thaw()?;
+        }
+        result?;
+
+        self.wait_until("empty", || {
+            let procs = self.get_all_procs()?;
+            let empty = procs.is_empty();
+            cgroup::kill_procs(procs)?;
+
+            Ok(empty)
+        })
+    }
+
+    fn remove_recursive(&self) -> Result<()> {
+        info!("removing cgroup subtree");
+        self.kill_all()?;
+
+        for dir in self.get_dirs().into_iter().filter(|dir| dir.is_dir()) {
+            for path in CGroup::get_descendant_dirs(dir)?.iter().rev() {
+                fs::remove_dir(path).map_err(|e| Error::cgroup_io(path, e))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn read_file(path: &Path) -> Result<String> {
+    info!("reading {}", path.display());
+    fs::read_to_string(path).map_err(|e| Error::cgroup_io(path, e))
+}
+
+fn write_file(path: &Path, data: &str) -> Result<()> {
+    info!("writing {} to {}", data, path.display());
+    File::options()
+        .append(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(data.as_bytes()))
+        .map_err(|e| Error::cgroup_io(path, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process;
+
+    use super::{super::cpuset::CpusetPartition, *};
+
+    /// Fake legacy hierarchies in a temporary directory, one directory per
+    /// mount, with interface files as plain files that writes append to.
+    struct Hierarchies(PathBuf);
+
+    impl Hierarchies {
+        fn new(name: &str) -> Self {
+            let path =
+                std::env::temp_dir().join(format!("izoli-cgroup-v1-{}-{}", name, process::id()));
+            let _ = fs::remove_dir_all(&path);
+            fs::create_dir_all(&path).unwrap();
+            Self(path)
+        }
+
+        /// `cpu` and `cpuacct` share a mount, as on most hosts.
+        fn mounts(&self) -> LegacyMounts {
+            [
+                ("cpu", "cpu,cpuacct"),
+                ("cpuacct", "cpu,cpuacct"),
+                ("cpuset", "cpuset"),
+                ("memory", "memory"),
+                ("pids", "pids"),
+            ]
+            .into_iter()
+            .map(|(controller, mount)| (controller.to_string(), self.0.join(mount)))
+            .collect()
+        }
+
+        /// `path` in every hierarchy but memory, where the supervisor sits in
+        /// a group of its own.
+        fn paths(&self, path: &str) -> LegacyPaths {
+            self.mounts()
+                .into_keys()
+                .map(|controller| {
+                    let path = match controller.as_str() {
+                        "memory" => Path::new("/own").join(path),
+                        _ => Path::new("/").join(path),
+                    };
+                    (controller, path)
+                })
+                .collect()
+        }
+
+        fn file(&self, path: &str, content: &str) {
+            let path = self.0.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+
+        fn read(&self, path: &str) -> String {
+            fs::read_to_string(self.0.join(path)).unwrap()
+        }
+    }
+
+    impl Drop for Hierarchies {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// Makes `box` (under `izoli`) ready for writes in every hierarchy.
+    fn make_box(hierarchies: &Hierarchies) {
+        hierarchies.file("cpuset/cpuset.cpus", "0-3\n");
+        hierarchies.file("cpuset/cpuset.mems", "0\n");
+        for dir in ["cpu,cpuacct", "cpuset", "memory/own", "pids"] {
+            for name in ["cgroup.procs", "cpuset.cpus", "cpuset.mems"] {
+                hierarchies.file(&format!("{}/izoli/box/{}", dir, name), "");
+            }
+            hierarchies.file(&format!("{}/izoli/cpuset.cpus", dir), "");
+            hierarchies.file(&format!("{}/izoli/cpuset.mems", dir), "");
+        }
+    }
+
+    #[test]
+    fn new_creates_the_group_in_each_hierarchy() {
+        let hierarchies = Hierarchies::new("new");
+        hierarchies.file("cpuset/cpuset.cpus", "0-3\n");
+        hierarchies.file("cpuset/cpuset.mems", "0\n");
+        for name in ["cpuset.cpus", "cpuset.mems"] {
+            hierarchies.file(&format!("cpuset/izoli/{}", name), "");
+            hierarchies.file(&format!("cpuset/izoli/box/{}", name), "");
+        }
+
+        let cgroup = CGroupV1::new(
+            hierarchies.mounts(),
+            hierarchies.paths("izoli/box"),
+            &[Controller::Cpu, Controller::Memory],
+        )
+        .unwrap();
+
+        assert_eq!(
+            cgroup.get_dirs(),
+            [
+                hierarchies.0.join("cpu,cpuacct/izoli/box"),
+                hierarchies.0.join("cpuset/izoli/box"),
+                hierarchies.0.join("memory/own/izoli/box"),
+                hierarchies.0.join("pids/izoli/box"),
+            ]
+        );
+        assert!(cgroup.get_dirs().iter().all(|dir| dir.is_dir()));
+        assert_eq!(hierarchies.read("cpuset/izoli/cpuset.cpus"), "0-3");
+        assert_eq!(hierarchies.read("cpuset/izoli/box/cpuset.cpus"), "0-3");
+        assert_eq!(hierarchies.read("cpuset/izoli/box/cpuset.mems"), "0");
+
+        // The memory and pids groups were created here, so they go with the
+        // handle.
+        drop(cgroup);
+        assert!(!hierarchies.0.join("memory/own/izoli/box").exists());
+        assert!(!hierarchies.0.join("pids/izoli/box").exists());
+    }
+
+    #[test]
+    fn new_refuses_controllers_without_a_hierarchy() {
+        let hierarchies = Hierarchies::new("unavailable");
+
+        match CGroupV1::new(
+            hierarchies.mounts(),
+            hierarchies.paths("izoli/box"),
+            &[Controller::Hugetlb],
+        ) {
+            Err(Error::ControllerUnavailable { controller, .. }) => {
+                assert_eq!(controller, Controller::Hugetlb)
+            }
+            result => panic!("unexpected {:?}", result.map(|_| ())),
+        }
+        assert!(!hierarchies.0.join("pids/izoli").exists());
+    }
+
+    #[test]
+    fn open_needs_an_existing_group() {
+        let hierarchies = Hierarchies::new("open");
+        assert!(CGroupV1::open(hierarchies.mounts(), hierarchies.paths("izoli/box")).is_err());
+
+        make_box(&hierarchies);
+        let cgroup = CGroupV1::open(hierarchies.mounts(), hierarchies.paths("izoli/box")).unwrap();
+        assert_eq!(
+            cgroup.get_dir("memory").unwrap(),
+            hierarchies.0.join("memory/own/izoli/box")
+        );
+        assert!(matches!(
+            cgroup.get_dir("freezer"),
+            Err(Error::Unsupported {
+                feature: "freezer",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn apply_options_writes_the_v1_files() {
+        let hierarchies = Hierarchies::new("apply");
+        make_box(&hierarchies);
+        for name in [
+            "cpu,cpuacct/izoli/box/cpu.cfs_period_us",
+            "cpu,cpuacct/izoli/box/cpu.cfs_quota_us",
+            "memory/own/izoli/box/memory.limit_in_bytes",
+            "pids/izoli/box/pids.max",
+        ] {
+            hierarchies.file(name, "");
+        }
+        let cgroup = CGroupV1::open(hierarchies.mounts(), hierarchies.paths("izoli/box")).unwrap();
+
+        cgroup
+            .apply_options(&CGroupOption {
+                cpu_max: Some(CGroupLimitValue::Value("0.5".parse().unwrap())),
+                memory_max: Some(CGroupLimitValue::Max),
+                pids_max: Some(CGroupLimitValue::Value(10)),
+                cpus: Some("1".parse().unwrap()),
+                ..Default::default()
+            })
+            .unwrap();
+
+        assert_eq!(
+            hierarchies.read("cpu,cpuacct/izoli/box/cpu.cfs_period_us"),
+            "100000"
+        );
+        assert_eq!(
+            hierarchies.read("cpu,cpuacct/izoli/box/cpu.cfs_quota_us"),
+            "50000"
+        );
+        assert_eq!(
+            hierarchies.read("memory/own/izoli/box/memory.limit_in_bytes"),
+            "-1"
+        );
+        assert_eq!(hierarchies.read("pids/izoli/box/pids.max"), "10");
+        assert_eq!(hierarchies.read("cpuset/izoli/box/cpuset.cpus"), "1");
+
+        assert!(matches!(
+            cgroup.apply_options(&CGroupOption {
+                cpus_partition: Some(CpusetPartition::Root),
+                ..Default::default()
+            }),
+            Err(Error::Unsupported {
+                feature: "cpuset.cpus.partition",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn add_procs_writes_once_per_hierarchy() {
+        let hierarchies = Hierarchies::new("procs");
+        make_box(&hierarchies);
+        let cgroup = CGroupV1::open(hierarchies.mounts(), hierarchies.paths("izoli/box")).unwrap();
+
+        cgroup.add_procs(vec![42]).unwrap();
+
+        for dir in ["cpu,cpuacct", "cpuset", "memory/own", "pids"] {
+            assert_eq!(
+                hierarchies.read(&format!("{}/izoli/box/cgroup.procs", dir)),
+                "42"
+            );
+        }
+        assert_eq!(cgroup.get_procs().unwrap(), [42]);
+    }
+
+    #[test]
+    fn kill_all_signals_processes_appearing_while_waiting() {
+        let hierarchies = Hierarchies::new("kill");
+        make_box(&hierarchies);
+        let procs = hierarchies.0.join("pids/izoli/box/cgroup.procs");
+        let mut first = process::Command::new("sleep").arg("60").spawn().unwrap();
+        let mut second = process::Command::new("sleep").arg("60").spawn().unwrap();
+        fs::write(&procs, first.id().to_string()).unwrap();
+
+        // Stands in for the kernel: once the first process dies, the second,
+        // as if forked just before, takes its place.
+        let second_id = second.id();
+        let kernel = thread::spawn(move || {
+            first.wait().unwrap();
+            fs::write(&procs, second_id.to_string()).unwrap();
+            let status = second.wait().unwrap();
+            fs::write(&procs, "").unwrap();
+            status
+        });
+
+        let cgroup = CGroupV1::open(hierarchies.mounts(), hierarchies.paths("izoli/box")).unwrap();
+        cgroup.kill_all().unwrap();
+
+        assert!(!kernel.join().unwrap().success());
+    }
+}
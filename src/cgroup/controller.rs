@@ -20,7 +20,7 @@ impl FromStr for Controller {
     type Err = ParseControllerError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match &*s {
+        match s {
             "cpu" => Ok(Self::Cpu),
             "cpuset" => Ok(Self::Cpuset),
             "memory" => Ok(Self::Memory),
@@ -34,6 +34,16 @@ impl FromStr for Controller {
     }
 }
 
+impl Controller {
+    /// Whether the controller can be enabled in a threaded subtree.
+    pub fn is_threaded(&self) -> bool {
+        matches!(
+            self,
+            Controller::Cpu | Controller::Cpuset | Controller::Pids
+        )
+    }
+}
+
 impl fmt::Display for Controller {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
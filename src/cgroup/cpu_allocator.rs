@@ -0,0 +1,253 @@
+use std::{
+    collections::HashMap,
+    fs,
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+
+use tracing::info;
+
+use crate::error::{Error, Result};
+
+use super::{cgroup::CGroup, cpuset::CpuSet};
+
+pub type SharedCpuAllocator = Arc<Mutex<CpuAllocator>>;
+
+/// Asks for CPUs from a [`CpuAllocator`] when a box is created; they are given
+/// back when it is torn down.
+#[derive(Debug, Clone)]
+pub struct CpuRequest {
+    pub allocator: SharedCpuAllocator,
+    /// Number of CPUs the box gets.
+    pub count: usize,
+    /// Give every CPU on its own physical core and keep the core's SMT
+    /// siblings idle, so no other box shares the core.
+    pub whole_cores: bool,
+}
+
+/// Hands out disjoint sets of CPUs to boxes running side by side.
+#[derive(Debug, Clone)]
+pub struct CpuAllocator {
+    /// Physical cores, each the set of its hardware threads that can be
+    /// handed out.
+    cores: Vec<CpuSet>,
+    /// CPUs held by each box, including idle siblings reserved for it.
+    allocations: HashMap<usize, Allocation>,
+}
+
+#[derive(Debug, Clone)]
+struct Allocation {
+    cpus: CpuSet,
+    reserved: CpuSet,
+}
+
+impl CpuAllocator {
+    /// Creates an allocator over the CPUs effectively available to `parent`,
+    /// the group boxes are created under.
+    pub fn new(parent: &CGroup) -> Result<Self> {
+        Ok(Self::from_cpus(&parent.get_cpuset_cpus_effective()?))
+    }
+
+    /// Creates an allocator over `cpus`, grouping them into physical cores
+    /// using `/sys/devices/system/cpu/cpu*/topology`.
+    pub fn from_cpus(cpus: &CpuSet) -> Self {
+        let mut cores: Vec<CpuSet> = Vec::new();
+
+        for cpu in cpus.iter() {
+            if cores.iter().any(|core| core.contains(cpu)) {
+                continue;
+            }
+
+            let siblings = Self::get_thread_siblings(cpu)
+                .map(|siblings| siblings.intersection(cpus))
+                .filter(|siblings| siblings.contains(cpu))
+                .unwrap_or_else(|| CpuSet::from_iter([cpu]));
+            cores.push(siblings);
+        }
+
+        Self::from_cores(cores)
+    }
+
+    /// Creates an allocator over physical cores given directly, each the set
+    /// of its hardware threads, instead of reading the topology from sysfs.
+    /// The cores must not overlap.
+    pub fn from_cores(cores: Vec<CpuSet>) -> Self {
+        Self {
+            cores,
+            allocations: HashMap::new(),
+        }
+    }
+
+    pub fn shared(self) -> SharedCpuAllocator {
+        Arc::new(Mutex::new(self))
+    }
+
+    fn get_thread_siblings(cpu: u32) -> Option<CpuSet> {
+        let path = format!(
+            "/sys/devices/system/cpu/cpu{}/topology/thread_siblings_list",
+            cpu
+        );
+
+        CpuSet::from_str(&fs::read_to_string(path).ok()?).ok()
+    }
+
+    /// Every CPU the allocator manages.
+    pub fn get_cpus(&self) -> CpuSet {
+        self.cores
+            .iter()
+            .fold(CpuSet::new(), |cpus, core| cpus.union(core))
+    }
+
+    /// CPUs neither allocated nor reserved.
+    pub fn get_free(&self) -> CpuSet {
+        self.allocations
+            .values()
+            .fold(self.get_cpus(), |free, allocation| {
+                free.difference(&allocation.cpus)
+                    .difference(&allocation.reserved)
+            })
+    }
+
+    /// Allocates `count` CPUs to box `id`. With `whole_cores`, each CPU comes
+    /// from a different, otherwise unused physical core whose remaining
+    /// siblings are reserved for the box as well. A box holding CPUs already
+    /// gets its existing allocation back.
+    pub fn allocate(&mut self, id: usize, count: usize, whole_cores: bool) -> Result<CpuSet> {
+        if let Some(allocation) = self.allocations.get(&id) {
+            return Ok(allocation.cpus.clone());
+        }
+
+        let free = self.get_free();
+        let mut cpus = CpuSet::new();
+        let mut reserved = CpuSet::new();
+
+        if whole_cores {
+            for core in self
+                .cores
+                .iter()
+                .filter(|core| core.is_subset(&free))
+                .take(count)
+            {
+                let mut threads = core.iter();
+                if let Some(cpu) = threads.next() {
+                    cpus.insert(cpu);
+                }
+                threads.for_each(|sibling| {
+                    reserved.insert(sibling);
+                });
+            }
+        } else {
+            // Fill partially used cores first to keep whole cores available.
+            let mut cores: Vec<CpuSet> = self
+                .cores
+                .iter()
+                .map(|core| core.intersection(&free))
+                .filter(|core| !core.is_empty())
+                .collect();
+            cores.sort_by_key(|core| core.len());
+            cores
+                .iter()
+                .flat_map(|core| core.iter())
+                .take(count)
+                .for_each(|cpu| {
+                    cpus.insert(cpu);
+                });
+        }
+
+        if cpus.len() < count {
+            return Err(Error::InsufficientCpus {
+                requested: count,
+                available: cpus.len(),
+            });
+        }
+
+        info!("allocated cpus {} to box {}", cpus, id);
+        self.allocations.insert(
+            id,
+            Allocation {
+                cpus: cpus.clone(),
+                reserved,
+            },
+        );
+
+        Ok(cpus)
+    }
+
+    /// Returns the CPUs held by box `id` to the pool.
+    pub fn release(&mut self, id: usize) -> Option<CpuSet> {
+        let allocation = self.allocations.remove(&id)?;
+        info!("released cpus {} from box {}", allocation.cpus, id);
+
+        Some(allocation.cpus)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Four cores with two threads each, numbered like most x86 hosts: CPU
+    /// `n` and `n + 4` are siblings.
+    fn allocator() -> CpuAllocator {
+        CpuAllocator::from_cores(
+            ["0,4", "1,5", "2,6", "3,7"]
+                .iter()
+                .map(|core| core.parse().unwrap())
+                .collect(),
+        )
+    }
+
+    fn cpus(s: &str) -> CpuSet {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn allocate_packs_siblings_together() {
+        let mut allocator = allocator();
+
+        assert_eq!(allocator.allocate(1, 1, false).unwrap(), cpus("0"));
+        assert_eq!(allocator.allocate(2, 1, false).unwrap(), cpus("4"));
+        assert_eq!(allocator.allocate(3, 2, false).unwrap(), cpus("1,5"));
+        assert_eq!(allocator.allocate(1, 3, false).unwrap(), cpus("0"));
+        assert_eq!(allocator.get_free(), cpus("2-3,6-7"));
+    }
+
+    #[test]
+    fn whole_cores_reserve_their_siblings() {
+        let mut allocator = allocator();
+
+        assert_eq!(allocator.allocate(1, 2, true).unwrap(), cpus("0-1"));
+        assert_eq!(allocator.get_free(), cpus("2-3,6-7"));
+        assert_eq!(allocator.allocate(2, 1, false).unwrap(), cpus("2"));
+
+        // Core 2,6 is partly taken, leaving one whole core.
+        match allocator.allocate(3, 2, true) {
+            Err(Error::InsufficientCpus {
+                requested,
+                available,
+            }) => assert_eq!((requested, available), (2, 1)),
+            result => panic!("unexpected {:?}", result),
+        }
+        assert_eq!(allocator.get_free(), cpus("3,6-7"));
+    }
+
+    #[test]
+    fn release_makes_cpus_available_again() {
+        let mut allocator = allocator();
+        allocator.allocate(1, 2, true).unwrap();
+        allocator.allocate(2, 4, false).unwrap();
+
+        assert!(matches!(
+            allocator.allocate(3, 1, false),
+            Err(Error::InsufficientCpus {
+                requested: 1,
+                available: 0
+            })
+        ));
+
+        assert_eq!(allocator.release(1), Some(cpus("0-1")));
+        assert_eq!(allocator.release(1), None);
+        assert_eq!(allocator.get_free(), cpus("0-1,4-5"));
+        assert_eq!(allocator.allocate(3, 2, true).unwrap(), cpus("0-1"));
+    }
+}
@@ -1,17 +1,45 @@
 use core::fmt;
 use std::str::FromStr;
 
-use super::limit_value::CGroupLimitValue;
+use super::{
+    limit_value::CGroupLimitValue,
+    units::{scale_decimal, split_unit, TimeSpan},
+};
 
-#[derive(Debug, Clone, Copy)]
+/// The period `cpu.max` uses unless told otherwise.
+pub const DEFAULT_CPU_PERIOD: TimeSpan = TimeSpan::from_micros(100_000);
+
+/// The contents of `cpu.max`: the CPU time the group may use in each period,
+/// written in microseconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct CpuLimit {
-    pub max: CGroupLimitValue<u64>,
-    pub period: u64,
+    pub max: CGroupLimitValue<TimeSpan>,
+    pub period: TimeSpan,
 }
 
 #[derive(Debug, PartialEq, Eq)]
 pub struct ParseCpuLimitError;
 
+impl CpuLimit {
+    /// Limits the group to `quota` CPUs in each `period`.
+    pub fn from_quota(quota: CGroupLimitValue<CpuQuota>, period: TimeSpan) -> Self {
+        let max = quota.map(|quota| {
+            TimeSpan::from_micros(period.as_micros() * quota.millicores / CpuQuota::CORE)
+        });
+
+        Self { max, period }
+    }
+
+    /// The number of CPUs this limit amounts to.
+    pub fn quota(&self) -> CGroupLimitValue<CpuQuota> {
+        let period = self.period.as_micros().max(1);
+
+        self.max.map(|max| CpuQuota {
+            millicores: max.as_micros() * CpuQuota::CORE / period,
+        })
+    }
+}
+
 impl FromStr for CpuLimit {
     type Err = ParseCpuLimitError;
 
@@ -29,7 +57,7 @@ impl FromStr for CpuLimit {
         };
 
         let period = if let Some(period) = spl.next() {
-            if let Ok(period) = u64::from_str(period) {
+            if let Ok(period) = TimeSpan::from_str(period) {
                 period
             } else {
                 return Err(ParseCpuLimitError);
@@ -44,6 +72,94 @@ impl FromStr for CpuLimit {
 
 impl fmt::Display for CpuLimit {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{} {}", self.max, self.period)
+        write!(
+            f,
+            "{} {}",
+            self.max.map(|max| max.as_micros()),
+            self.period.as_micros()
+        )
+    }
+}
+
+/// An amount of CPU time as a number of CPUs, e.g. `1.5`, `2 cores`, `500m`
+/// (millicores) or `150%`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct CpuQuota {
+    pub millicores: u64,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct ParseCpuQuotaError;
+
+impl CpuQuota {
+    const CORE: u64 = 1000;
+
+    pub fn from_cores(cores: u64) -> Self {
+        Self {
+            millicores: cores * Self::CORE,
+        }
+    }
+}
+
+impl FromStr for CpuQuota {
+    type Err = ParseCpuQuotaError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (number, unit) = split_unit(s.trim());
+
+        let scale = match unit.trim() {
+            "" | "core" | "cores" | "cpu" | "cpus" => Self::CORE,
+            "m" => 1,
+            "%" => Self::CORE / 100,
+            _ => return Err(ParseCpuQuotaError),
+        };
+
+        scale_decimal(number, scale as u128)
+            .and_then(|millicores| u64::try_from(millicores).ok())
+            .map(|millicores| Self { millicores })
+            .ok_or(ParseCpuQuotaError)
+    }
+}
+
+impl fmt::Display for CpuQuota {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let cores = self.millicores / Self::CORE;
+        let fraction = self.millicores % Self::CORE;
+
+        if fraction == 0 {
+            write!(f, "{}", cores)
+        } else {
+            let fraction = format!("{:03}", fraction);
+            write!(f, "{}.{}", cores, fraction.trim_end_matches('0'))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cpu_quota_parses_cores_millicores_and_percent() {
+        for (s, millicores) in [
+            ("1", 1000),
+            ("1.5", 1500),
+            ("500m", 500),
+            ("150%", 1500),
+            ("2 cpus", 2000),
+        ] {
+            assert_eq!(s.parse(), Ok(CpuQuota { millicores }), "{}", s);
+        }
+        for s in ["", "1x", "m", "1.2.3"] {
+            assert_eq!(s.parse::<CpuQuota>(), Err(ParseCpuQuotaError), "{}", s);
+        }
+    }
+
+    #[test]
+    fn cpu_quota_round_trips() {
+        for s in ["0", "1", "1.5", "0.25", "0.001", "12"] {
+            let quota: CpuQuota = s.parse().unwrap();
+            assert_eq!(quota.to_string(), s);
+        }
     }
 }
@@ -0,0 +1,247 @@
+use std::{collections::BTreeSet, fmt, str::FromStr};
+
+/// A set of CPU (or memory node) numbers in the kernel's list format, e.g.
+/// `0-3,8`, as used by `cpuset.cpus` and `cpuset.mems`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct CpuSet {
+    cpus: BTreeSet<u32>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct ParseCpuSetError;
+
+impl CpuSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, cpu: u32) -> bool {
+        self.cpus.insert(cpu)
+    }
+
+    pub fn remove(&mut self, cpu: u32) -> bool {
+        self.cpus.remove(&cpu)
+    }
+
+    pub fn contains(&self, cpu: u32) -> bool {
+        self.cpus.contains(&cpu)
+    }
+
+    pub fn len(&self) -> usize {
+        self.cpus.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cpus.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = u32> + '_ {
+        self.cpus.iter().copied()
+    }
+
+    pub fn union(&self, other: &CpuSet) -> CpuSet {
+        self.cpus.union(&other.cpus).copied().collect()
+    }
+
+    pub fn difference(&self, other: &CpuSet) -> CpuSet {
+        self.cpus.difference(&other.cpus).copied().collect()
+    }
+
+    pub fn intersection(&self, other: &CpuSet) -> CpuSet {
+        self.cpus.intersection(&other.cpus).copied().collect()
+    }
+
+    pub fn is_disjoint(&self, other: &CpuSet) -> bool {
+        self.cpus.is_disjoint(&other.cpus)
+    }
+
+    pub fn is_subset(&self, other: &CpuSet) -> bool {
+        self.cpus.is_subset(&other.cpus)
+    }
+}
+
+impl FromIterator<u32> for CpuSet {
+    fn from_iter<I: IntoIterator<Item = u32>>(iter: I) -> Self {
+        Self {
+            cpus: iter.into_iter().collect(),
+        }
+    }
+}
+
+impl FromStr for CpuSet {
+    type Err = ParseCpuSetError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut cpus = BTreeSet::new();
+
+        for range in s.trim().split(',').filter(|range| !range.is_empty()) {
+            let (start, end) = match range.split_once('-') {
+                Some((start, end)) => (start, end),
+                None => (range, range),
+            };
+
+            let start = u32::from_str(start.trim()).map_err(|_| ParseCpuSetError)?;
+            let end = u32::from_str(end.trim()).map_err(|_| ParseCpuSetError)?;
+            if start > end {
+                return Err(ParseCpuSetError);
+            }
+
+            cpus.extend(start..=end);
+        }
+
+        Ok(Self { cpus })
+    }
+}
+
+impl fmt::Display for CpuSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut cpus = self.cpus.iter().copied().peekable();
+        let mut first = true;
+
+        while let Some(start) = cpus.next() {
+            let mut end = start;
+            while cpus.peek() == Some(&(end + 1)) {
+                end = cpus.next().unwrap();
+            }
+
+            if !first {
+                write!(f, ",")?;
+            }
+            first = false;
+
+            if start == end {
+                write!(f, "{}", start)?;
+            } else {
+                write!(f, "{}-{}", start, end)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The state of `cpuset.cpus.partition`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CpusetPartition {
+    /// A regular cpuset sharing CPUs with its parent.
+    Member,
+    /// A partition root owning its exclusive CPUs, with load balancing.
+    Root,
+    /// A partition root owning its exclusive CPUs, without load balancing.
+    Isolated,
+    /// A root partition the kernel could not honor, with the reason given.
+    RootInvalid(String),
+    /// An isolated partition the kernel could not honor, with the reason given.
+    IsolatedInvalid(String),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct ParseCpusetPartitionError;
+
+impl CpusetPartition {
+    /// Whether the kernel could not honor the partition.
+    pub fn is_invalid(&self) -> bool {
+        matches!(
+            self,
+            CpusetPartition::RootInvalid(_) | CpusetPartition::IsolatedInvalid(_)
+        )
+    }
+}
+
+impl FromStr for CpusetPartition {
+    type Err = ParseCpusetPartitionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (kind, reason) = match s.split_once(" invalid") {
+            Some((kind, reason)) => {
+                let reason = reason
+                    .trim()
+                    .trim_start_matches('(')
+                    .trim_end_matches(')')
+                    .to_string();
+                (kind, Some(reason))
+            }
+            None => (s, None),
+        };
+
+        match (kind, reason) {
+            ("member", None) => Ok(Self::Member),
+            ("root", None) => Ok(Self::Root),
+            ("isolated", None) => Ok(Self::Isolated),
+            ("root", Some(reason)) => Ok(Self::RootInvalid(reason)),
+            ("isolated", Some(reason)) => Ok(Self::IsolatedInvalid(reason)),
+            _ => Err(ParseCpusetPartitionError),
+        }
+    }
+}
+
+impl fmt::Display for CpusetPartition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CpusetPartition::Member => write!(f, "member"),
+            CpusetPartition::Root => write!(f, "root"),
+            CpusetPartition::Isolated => write!(f, "isolated"),
+            CpusetPartition::RootInvalid(reason) => write!(f, "root invalid ({})", reason),
+            CpusetPartition::IsolatedInvalid(reason) => {
+                write!(f, "isolated invalid ({})", reason)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cpuset_parses_ranges_and_single_cpus() {
+        let cpus: CpuSet = "0-2,5,7-8\n".parse().unwrap();
+        assert_eq!(cpus.iter().collect::<Vec<_>>(), [0, 1, 2, 5, 7, 8]);
+        assert_eq!("".parse::<CpuSet>().unwrap(), CpuSet::new());
+        assert_eq!("3,1,2".parse::<CpuSet>().unwrap().to_string(), "1-3");
+    }
+
+    #[test]
+    fn cpuset_rejects_malformed_lists() {
+        for s in ["3-1", "a", "1-", "-1", "1-2-3", "1;2"] {
+            assert_eq!(s.parse::<CpuSet>(), Err(ParseCpuSetError), "{}", s);
+        }
+    }
+
+    #[test]
+    fn cpuset_round_trips() {
+        for s in ["0", "0-3", "0,2,4", "0-1,3,5-7", "1-2,64-127"] {
+            let cpus: CpuSet = s.parse().unwrap();
+            assert_eq!(cpus.to_string(), s);
+            assert_eq!(cpus.to_string().parse::<CpuSet>().unwrap(), cpus);
+        }
+        assert_eq!(CpuSet::new().to_string(), "");
+    }
+
+    #[test]
+    fn partition_round_trips() {
+        for partition in [
+            CpusetPartition::Member,
+            CpusetPartition::Root,
+            CpusetPartition::Isolated,
+            CpusetPartition::RootInvalid("Parent is an invalid partition root".to_string()),
+            CpusetPartition::IsolatedInvalid("Cpu list in cpuset.cpus not exclusive".to_string()),
+        ] {
+            let parsed: CpusetPartition = partition.to_string().parse().unwrap();
+            assert_eq!(parsed, partition);
+        }
+        assert_eq!(
+            "root invalid (Invalid cpu list in cpuset.cpus.exclusive)\n".parse(),
+            Ok(CpusetPartition::RootInvalid(
+                "Invalid cpu list in cpuset.cpus.exclusive".to_string()
+            ))
+        );
+        assert_eq!(
+            "bogus".parse::<CpusetPartition>(),
+            Err(ParseCpusetPartitionError)
+        );
+        assert!(!CpusetPartition::Root.is_invalid());
+        assert!(CpusetPartition::IsolatedInvalid(String::new()).is_invalid());
+    }
+}
@@ -0,0 +1,130 @@
+use std::{fmt, str::FromStr};
+
+const KB: u64 = 1 << 10;
+const MB: u64 = 1 << 20;
+const GB: u64 = 1 << 30;
+
+/// A huge page size, named the way hugetlb interface files are, e.g. the
+/// `2MB` in `hugetlb.2MB.max`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct HugePageSize {
+    pub bytes: u64,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct ParseHugePageSizeError;
+
+impl FromStr for HugePageSize {
+    type Err = ParseHugePageSizeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (number, unit) = s.split_at(s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len()));
+        let number = u64::from_str(number).map_err(|_| ParseHugePageSizeError)?;
+
+        let unit = match unit {
+            "KB" => KB,
+            "MB" => MB,
+            "GB" => GB,
+            _ => return Err(ParseHugePageSizeError),
+        };
+
+        let bytes = number.checked_mul(unit).ok_or(ParseHugePageSizeError)?;
+        Ok(Self { bytes })
+    }
+}
+
+impl fmt::Display for HugePageSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.bytes % GB == 0 {
+            write!(f, "{}GB", self.bytes / GB)
+        } else if self.bytes % MB == 0 {
+            write!(f, "{}MB", self.bytes / MB)
+        } else {
+            write!(f, "{}KB", self.bytes / KB)
+        }
+    }
+}
+
+/// The contents of `hugetlb.<size>.events`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HugetlbEvents {
+    /// Allocations that failed because of `hugetlb.<size>.max`.
+    pub max: u64,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct ParseHugetlbEventsError;
+
+impl FromStr for HugetlbEvents {
+    type Err = ParseHugetlbEventsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut events = Self { max: 0 };
+
+        for line in s.lines() {
+            let mut spl = line.split_whitespace();
+            if let (Some("max"), Some(value)) = (spl.next(), spl.next()) {
+                events.max = u64::from_str(value).map_err(|_| ParseHugetlbEventsError)?;
+            }
+        }
+
+        Ok(events)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn huge_page_size_round_trips() {
+        for (name, bytes) in [
+            ("64KB", 64 * KB),
+            ("2MB", 2 * MB),
+            ("32MB", 32 * MB),
+            ("1GB", GB),
+        ] {
+            let size: HugePageSize = name.parse().unwrap();
+            assert_eq!(size.bytes, bytes);
+            assert_eq!(size.to_string(), name);
+        }
+        assert_eq!(HugePageSize { bytes: 1024 * MB }.to_string(), "1GB");
+    }
+
+    #[test]
+    fn huge_page_size_rejects_unknown_units() {
+        for s in ["2", "2M", "2mb", "MB", "2 MB", "-2MB"] {
+            assert_eq!(
+                s.parse::<HugePageSize>(),
+                Err(ParseHugePageSizeError),
+                "{}",
+                s
+            );
+        }
+    }
+
+    #[test]
+    fn huge_page_size_rejects_sizes_past_u64() {
+        assert_eq!(
+            "17179869184GB".parse::<HugePageSize>(),
+            Err(ParseHugePageSizeError)
+        );
+        assert_eq!(
+            "17179869183GB".parse(),
+            Ok(HugePageSize {
+                bytes: 17179869183 << 30
+            })
+        );
+    }
+
+    #[test]
+    fn hugetlb_events_read_max() {
+        assert_eq!("max 3\n".parse(), Ok(HugetlbEvents { max: 3 }));
+        assert_eq!("".parse(), Ok(HugetlbEvents { max: 0 }));
+        assert_eq!(
+            "max x\n".parse::<HugetlbEvents>(),
+            Err(ParseHugetlbEventsError)
+        );
+    }
+}
@@ -1,6 +1,6 @@
 use std::{fmt, str::FromStr};
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum CGroupLimitValue<T>
 where
     T: FromStr + std::fmt::Display,
@@ -12,6 +12,23 @@ where
 #[derive(Debug, PartialEq, Eq)]
 pub struct ParseCGroupLimitValueError;
 
+impl<T> CGroupLimitValue<T>
+where
+    T: FromStr + std::fmt::Display,
+{
+    /// Converts the value, keeping `max` as is.
+    pub fn map<U, F>(self, f: F) -> CGroupLimitValue<U>
+    where
+        U: FromStr + std::fmt::Display,
+        F: FnOnce(T) -> U,
+    {
+        match self {
+            CGroupLimitValue::Max => CGroupLimitValue::Max,
+            CGroupLimitValue::Value(value) => CGroupLimitValue::Value(f(value)),
+        }
+    }
+}
+
 impl<T> FromStr for CGroupLimitValue<T>
 where
     T: FromStr + std::fmt::Display,
@@ -22,7 +39,7 @@ where
         if s.trim() == "max" {
             Ok(Self::Max)
         } else {
-            if let Ok(value) = T::from_str(s) {
+            if let Ok(value) = T::from_str(s.trim()) {
                 Ok(Self::Value(value))
             } else {
                 Err(ParseCGroupLimitValueError)
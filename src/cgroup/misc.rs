@@ -0,0 +1,78 @@
+use std::{collections::BTreeMap, fmt, str::FromStr};
+
+/// Per-resource values of the misc controller, one `<resource> <value>` pair
+/// per line, as in `misc.max`, `misc.current` and `misc.events`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MiscValues<T> {
+    pub values: BTreeMap<String, T>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct ParseMiscValuesError;
+
+impl<T> MiscValues<T> {
+    pub fn get(&self, resource: &str) -> Option<&T> {
+        self.values.get(resource)
+    }
+}
+
+impl<T> FromStr for MiscValues<T>
+where
+    T: FromStr,
+{
+    type Err = ParseMiscValuesError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut values = BTreeMap::new();
+
+        for line in s.lines() {
+            let mut spl = line.split_whitespace();
+            let (Some(resource), Some(value)) = (spl.next(), spl.next()) else {
+                continue;
+            };
+
+            let value = T::from_str(value).map_err(|_| ParseMiscValuesError)?;
+            values.insert(resource.to_string(), value);
+        }
+
+        Ok(Self { values })
+    }
+}
+
+impl<T> fmt::Display for MiscValues<T>
+where
+    T: fmt::Display,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (resource, value) in &self.values {
+            writeln!(f, "{} {}", resource, value)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cgroup::limit_value::CGroupLimitValue;
+
+    #[test]
+    fn misc_values_round_trip() {
+        let content = "res_a 10\nres_b max\n";
+        let values: MiscValues<CGroupLimitValue<u64>> = content.parse().unwrap();
+        assert_eq!(values.get("res_a"), Some(&CGroupLimitValue::Value(10)));
+        assert_eq!(values.get("res_b"), Some(&CGroupLimitValue::Max));
+        assert_eq!(values.get("res_c"), None);
+        assert_eq!(values.to_string(), content);
+    }
+
+    #[test]
+    fn misc_values_reject_bad_values() {
+        assert_eq!(
+            "res_a ten\n".parse::<MiscValues<u64>>(),
+            Err(ParseMiscValuesError)
+        );
+        assert_eq!("".parse::<MiscValues<u64>>(), Ok(MiscValues::default()));
+    }
+}
@@ -1,6 +1,20 @@
+pub mod backend;
+#[allow(clippy::module_inception)]
 pub mod cgroup;
+pub mod cgroup_config;
+pub mod cgroup_events;
+pub mod cgroup_node;
 pub mod cgroup_option;
 pub mod cgroup_stat;
+pub mod cgroup_type;
+pub mod cgroup_usage;
+pub mod cgroup_v1;
 pub mod controller;
+pub mod cpu_allocator;
 pub mod cpu_limit;
+pub mod cpuset;
+pub mod hugetlb;
 pub mod limit_value;
+pub mod misc;
+pub mod rdma;
+pub mod units;
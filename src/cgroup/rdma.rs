@@ -0,0 +1,121 @@
+use std::{fmt, str::FromStr};
+
+use super::limit_value::CGroupLimitValue;
+
+/// One device line of `rdma.max` or `rdma.current`, e.g.
+/// `mlx4_0 hca_handle=2 hca_object=max`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RdmaLimit {
+    pub device: String,
+    pub hca_handle: CGroupLimitValue<u32>,
+    pub hca_object: CGroupLimitValue<u32>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct ParseRdmaLimitError;
+
+impl FromStr for RdmaLimit {
+    type Err = ParseRdmaLimitError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut spl = s.split_whitespace();
+        let device = spl.next().ok_or(ParseRdmaLimitError)?.to_string();
+        let mut hca_handle = None;
+        let mut hca_object = None;
+
+        for pair in spl {
+            let (key, value) = pair.split_once('=').ok_or(ParseRdmaLimitError)?;
+            let value = CGroupLimitValue::from_str(value).map_err(|_| ParseRdmaLimitError)?;
+
+            match key {
+                "hca_handle" => hca_handle = Some(value),
+                "hca_object" => hca_object = Some(value),
+                _ => return Err(ParseRdmaLimitError),
+            }
+        }
+
+        Ok(Self {
+            device,
+            hca_handle: hca_handle.ok_or(ParseRdmaLimitError)?,
+            hca_object: hca_object.ok_or(ParseRdmaLimitError)?,
+        })
+    }
+}
+
+impl fmt::Display for RdmaLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} hca_handle={} hca_object={}",
+            self.device, self.hca_handle, self.hca_object
+        )
+    }
+}
+
+/// Every device line of an rdma interface file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RdmaLimits {
+    pub devices: Vec<RdmaLimit>,
+}
+
+impl FromStr for RdmaLimits {
+    type Err = ParseRdmaLimitError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let devices = s
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(RdmaLimit::from_str)
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self { devices })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rdma_limit_round_trips() {
+        let line = "mlx4_0 hca_handle=2 hca_object=max";
+        let limit: RdmaLimit = line.parse().unwrap();
+        assert_eq!(
+            limit,
+            RdmaLimit {
+                device: "mlx4_0".to_string(),
+                hca_handle: CGroupLimitValue::Value(2),
+                hca_object: CGroupLimitValue::Max,
+            }
+        );
+        assert_eq!(limit.to_string(), line);
+    }
+
+    #[test]
+    fn rdma_limits_read_every_device() {
+        let limits: RdmaLimits =
+            "mlx4_0 hca_handle=2 hca_object=2000\nocrdma1 hca_handle=3 hca_object=max\n\n"
+                .parse()
+                .unwrap();
+        assert_eq!(limits.devices.len(), 2);
+        assert_eq!(limits.devices[1].device, "ocrdma1");
+    }
+
+    #[test]
+    fn rdma_limit_rejects_incomplete_lines() {
+        for line in [
+            "",
+            "mlx4_0",
+            "mlx4_0 hca_handle=2",
+            "mlx4_0 hca_handle=2 hca_object=x",
+            "mlx4_0 hca_handle=2 hca_object=1 other=3",
+        ] {
+            assert_eq!(
+                line.parse::<RdmaLimit>(),
+                Err(ParseRdmaLimitError),
+                "{}",
+                line
+            );
+        }
+    }
+}
@@ -0,0 +1,265 @@
+use std::{fmt, str::FromStr, time::Duration};
+
+/// A byte count written with binary suffixes, e.g. `512M`, `2G`, `1.5GiB` or
+/// `4096`. Prints with the largest suffix that divides it exactly.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Bytes(pub u64);
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct ParseBytesError;
+
+const BYTE_UNITS: [(&str, u64); 6] = [
+    ("E", 1 << 60),
+    ("P", 1 << 50),
+    ("T", 1 << 40),
+    ("G", 1 << 30),
+    ("M", 1 << 20),
+    ("K", 1 << 10),
+];
+
+impl Bytes {
+    pub fn as_u64(self) -> u64 {
+        self.0
+    }
+}
+
+impl From<u64> for Bytes {
+    fn from(bytes: u64) -> Self {
+        Self(bytes)
+    }
+}
+
+impl From<Bytes> for u64 {
+    fn from(bytes: Bytes) -> Self {
+        bytes.0
+    }
+}
+
+impl FromStr for Bytes {
+    type Err = ParseBytesError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (number, unit) = split_unit(s);
+
+        let unit = unit.trim();
+        let unit = unit
+            .strip_suffix("iB")
+            .or_else(|| unit.strip_suffix('B'))
+            .or_else(|| unit.strip_suffix('i'))
+            .unwrap_or(unit);
+
+        let scale = if unit.is_empty() {
+            1
+        } else {
+            BYTE_UNITS
+                .iter()
+                .find(|(suffix, _)| suffix.eq_ignore_ascii_case(unit))
+                .map(|(_, scale)| *scale)
+                .ok_or(ParseBytesError)?
+        };
+
+        scale_decimal(number, scale as u128)
+            .and_then(|bytes| u64::try_from(bytes).ok())
+            .map(Self)
+            .ok_or(ParseBytesError)
+    }
+}
+
+impl fmt::Display for Bytes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match BYTE_UNITS
+            .iter()
+            .find(|(_, scale)| self.0 != 0 && self.0 % scale == 0)
+        {
+            Some((suffix, scale)) => write!(f, "{}{}", self.0 / scale, suffix),
+            None => write!(f, "{}", self.0),
+        }
+    }
+}
+
+/// A [`Duration`] written with a unit, e.g. `250ms`, `1.5s` or `100us`. A
+/// bare number is taken as microseconds, the unit of the cgroup files.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TimeSpan(pub Duration);
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct ParseTimeSpanError;
+
+const TIME_UNITS: [(&str, u64); 7] = [
+    ("h", 3_600_000_000_000),
+    ("m", 60_000_000_000),
+    ("s", 1_000_000_000),
+    ("ms", 1_000_000),
+    ("us", 1_000),
+    ("µs", 1_000),
+    ("ns", 1),
+];
+
+impl TimeSpan {
+    pub const fn from_micros(micros: u64) -> Self {
+        Self(Duration::from_micros(micros))
+    }
+
+    pub fn as_micros(self) -> u64 {
+        self.0.as_micros() as u64
+    }
+}
+
+impl From<Duration> for TimeSpan {
+    fn from(duration: Duration) -> Self {
+        Self(duration)
+    }
+}
+
+impl From<TimeSpan> for Duration {
+    fn from(span: TimeSpan) -> Self {
+        span.0
+    }
+}
+
+impl FromStr for TimeSpan {
+    type Err = ParseTimeSpanError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (number, unit) = split_unit(s);
+
+        let scale = match unit.trim() {
+            "" => 1_000,
+            unit => TIME_UNITS
+                .iter()
+                .find(|(suffix, _)| *suffix == unit)
+                .map(|(_, scale)| *scale)
+                .ok_or(ParseTimeSpanError)?,
+        };
+
+        scale_decimal(number, scale as u128)
+            .and_then(|nanos| u64::try_from(nanos).ok())
+            .map(|nanos| Self(Duration::from_nanos(nanos)))
+            .ok_or(ParseTimeSpanError)
+    }
+}
+
+impl fmt::Display for TimeSpan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let nanos = self.0.as_nanos();
+
+        match [("s", 1_000_000_000), ("ms", 1_000_000), ("us", 1_000)]
+            .iter()
+            .find(|(_, scale)| nanos != 0 && nanos % scale == 0)
+        {
+            Some((suffix, scale)) => write!(f, "{}{}", nanos / scale, suffix),
+            None => write!(f, "{}ns", nanos),
+        }
+    }
+}
+
+/// Splits `1.5G` into the number `1.5` and the unit `G`.
+pub(crate) fn split_unit(s: &str) -> (&str, &str) {
+    let end = s
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(s.len());
+
+    s.split_at(end)
+}
+
+/// Multiplies a decimal number such as `1.5` by `scale` without going through
+/// floating point, truncating what is left below one.
+pub(crate) fn scale_decimal(number: &str, scale: u128) -> Option<u128> {
+    let (integer, fraction) = number.split_once('.').unwrap_or((number, ""));
+    if integer.is_empty() && fraction.is_empty() {
+        return None;
+    }
+
+    let integer: u128 = if integer.is_empty() {
+        0
+    } else {
+        integer.parse().ok()?
+    };
+    let fraction_value: u128 = if fraction.is_empty() {
+        0
+    } else {
+        fraction.parse().ok()?
+    };
+    let fraction_scale = 10u128.checked_pow(fraction.len() as u32)?;
+
+    integer
+        .checked_mul(scale)?
+        .checked_add(fraction_value.checked_mul(scale)? / fraction_scale)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bytes_parse_suffixes() {
+        for (s, bytes) in [
+            ("4096", 4096),
+            ("512M", 512 << 20),
+            ("512m", 512 << 20),
+            ("2G", 2 << 30),
+            ("2GB", 2 << 30),
+            ("1.5GiB", 3 << 29),
+            ("1.5 Gi", 3 << 29),
+            ("0.5K", 512),
+            (" 1T\n", 1 << 40),
+        ] {
+            assert_eq!(s.parse(), Ok(Bytes(bytes)), "{}", s);
+        }
+        for s in ["", "M", "1X", "1.2.3G", "-1", "16E"] {
+            assert_eq!(s.parse::<Bytes>(), Err(ParseBytesError), "{}", s);
+        }
+    }
+
+    #[test]
+    fn bytes_round_trip() {
+        for s in ["0", "1000", "4K", "1536K", "512M", "3G", "1T"] {
+            let bytes: Bytes = s.parse().unwrap();
+            assert_eq!(bytes.to_string(), s);
+        }
+        assert_eq!(Bytes(1 << 30).to_string(), "1G");
+        assert_eq!(Bytes(1025).to_string(), "1025");
+    }
+
+    #[test]
+    fn time_span_parse_units() {
+        for (s, duration) in [
+            ("100000", Duration::from_millis(100)),
+            ("250ms", Duration::from_millis(250)),
+            ("1.5s", Duration::from_millis(1500)),
+            ("100us", Duration::from_micros(100)),
+            ("100µs", Duration::from_micros(100)),
+            ("2m", Duration::from_secs(120)),
+            ("1h", Duration::from_secs(3600)),
+            ("10ns", Duration::from_nanos(10)),
+        ] {
+            assert_eq!(s.parse(), Ok(TimeSpan(duration)), "{}", s);
+        }
+        for s in ["", "s", "1x", "1.5.s", "-1s"] {
+            assert_eq!(s.parse::<TimeSpan>(), Err(ParseTimeSpanError), "{}", s);
+        }
+    }
+
+    #[test]
+    fn time_span_round_trips() {
+        for s in ["1s", "250ms", "100us", "10ns", "90s"] {
+            let span: TimeSpan = s.parse().unwrap();
+            assert_eq!(span.to_string(), s);
+            assert_eq!(span.to_string().parse::<TimeSpan>(), Ok(span));
+        }
+        assert_eq!(TimeSpan::from_micros(100_000).to_string(), "100ms");
+        assert_eq!(TimeSpan::default().to_string(), "0ns");
+    }
+
+    #[test]
+    fn scale_decimal_truncates_below_one() {
+        assert_eq!(scale_decimal("1.5", 1000), Some(1500));
+        assert_eq!(scale_decimal(".25", 100), Some(25));
+        assert_eq!(scale_decimal("3.", 10), Some(30));
+        assert_eq!(scale_decimal("0.0001", 1000), Some(0));
+        assert_eq!(scale_decimal(".", 10), None);
+        assert_eq!(scale_decimal("", 10), None);
+    }
+}
@@ -0,0 +1,205 @@
+use std::{fs, os::fd::AsRawFd};
+
+use nix::{
+    sys::stat::{fchmodat, makedev, mknodat, FchmodatFlags, Mode, SFlag},
+    unistd::symlinkat,
+};
+use tracing::info;
+
+use crate::{
+    cgroup::units::Bytes,
+    error::{Error, Result},
+    mount::{open_target, DevptsOptions, Mount, MountFlags, TargetKind, TmpfsOptions},
+};
+
+/// Size limit of the tmpfs holding `/dev` itself, which only contains nodes
+/// and symlinks.
+const DEV_SIZE: Bytes = Bytes(64 * 1024);
+
+/// Default size limit of `/dev/shm`, the same as the built-in `/tmp`.
+const DEFAULT_SHM_SIZE: Bytes = Bytes(64 * 1024 * 1024);
+
+/// How `/dev` is populated inside the box.
+#[derive(Debug, Clone)]
+pub struct DevOptions {
+    /// Device nodes made available, by default `null`, `zero`, `full`,
+    /// `random`, `urandom` and `tty`.
+    pub devices: Vec<Device>,
+    /// Size limit of `/dev/shm`, which is charged to the box's memory; 64M
+    /// by default, unlimited when `None`.
+    pub shm_size: Option<Bytes>,
+    /// Mount a devpts instance private to the box on `/dev/pts`, with
+    /// `/dev/ptmx` pointing into it.
+    pub devpts: bool,
+}
+
+/// A device node in `/dev`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Device {
+    /// Name of the node in `/dev`, e.g. `null`; a single path component.
+    pub name: String,
+    pub kind: DeviceKind,
+    pub major: u64,
+    pub minor: u64,
+    /// Permission bits, e.g. `0o666`.
+    pub mode: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceKind {
+    Char,
+    Block,
+}
+
+impl Device {
+    /// A character device readable and writable by everyone.
+    pub fn char(name: &str, major: u64, minor: u64) -> Self {
+        Self {
+            name: name.to_string(),
+            kind: DeviceKind::Char,
+            major,
+            minor,
+            mode: 0o666,
+        }
+    }
+}
+
+impl Default for DevOptions {
+    fn default() -> Self {
+        Self {
+            devices: vec![
+                Device::char("null", 1, 3),
+                Device::char("zero", 1, 5),
+                Device::char("full", 1, 7),
+                Device::char("random", 1, 8),
+                Device::char("urandom", 1, 9),
+                Device::char("tty", 5, 0),
+            ],
+            shm_size: Some(DEFAULT_SHM_SIZE),
+            devpts: true,
+        }
+    }
+}
+
+impl DevOptions {
+    /// Builds `/dev` in the box root directory `root`: a tmpfs holding the
+    /// allowed device nodes, the standard symlinks, `/dev/shm` and, if asked
+    /// for, a private devpts.
+    pub(crate) fn populate(&self, root: &str) -> Result<()> {
+        for device in &self.devices {
+            let name = device.name.as_str();
+            if name.is_empty() || name == "." || name == ".." || name.contains('/') {
+                return Err(Error::InvalidMount {
+                    target: format!("/dev/{}", name),
+                    reason: format!("device name {:?} is not a single path component", name),
+                });
+            }
+        }
+
+        // Unlike other tmpfs mounts this one must allow device nodes.
+        Mount::Tmpfs {
+            target: "/dev".to_string(),
+            options: TmpfsOptions {
+                size: Some(DEV_SIZE),
+                mode: Some(0o755),
+                ..Default::default()
+            },
+            flags: MountFlags {
+                no_suid: true,
+                no_exec: true,
+                ..Default::default()
+            },
+        }
+        .mount_in(root)?;
+        let dev = open_target(root, "/dev", TargetKind::Directory)?;
+
+        // Creating device nodes needs CAP_MKNOD in the initial user
+        // namespace; anywhere else the host's nodes are bind-mounted.
+        let bind = in_user_namespace();
+        for device in &self.devices {
+            let path = format!("/dev/{}", device.name);
+            info!("creating {}", path);
+
+            if bind {
+                Mount::Bind {
+                    source: path.clone(),
+                    target: path,
+                    flags: MountFlags::default(),
+                    recursive: false,
+                    target_kind: TargetKind::File,
+                }
+                .mount_in(root)?;
+            } else {
+                let kind = match device.kind {
+                    DeviceKind::Char => SFlag::S_IFCHR,
+                    DeviceKind::Block => SFlag::S_IFBLK,
+                };
+                mknodat(
+                    Some(dev.as_raw_fd()),
+                    device.name.as_str(),
+                    kind,
+                    Mode::empty(),
+                    makedev(device.major, device.minor),
+                )
+                .map_err(|e| Error::syscall("mknodat", &path, e))?;
+                // The mode passed to mknod is subject to the umask.
+                fchmodat(
+                    Some(dev.as_raw_fd()),
+                    device.name.as_str(),
+                    Mode::from_bits_truncate(device.mode),
+                    FchmodatFlags::FollowSymlink,
+                )
+                .map_err(|e| Error::syscall("fchmodat", &path, e))?;
+            }
+        }
+
+        let mut links = vec![
+            ("fd", "/proc/self/fd"),
+            ("stdin", "/proc/self/fd/0"),
+            ("stdout", "/proc/self/fd/1"),
+            ("stderr", "/proc/self/fd/2"),
+        ];
+        if self.devpts {
+            links.push(("ptmx", "pts/ptmx"));
+        }
+
+        for (name, target) in links {
+            symlinkat(target, Some(dev.as_raw_fd()), name)
+                .map_err(|e| Error::syscall("symlinkat", format!("/dev/{}", name), e))?;
+        }
+
+        Mount::tmpfs(
+            "/dev/shm",
+            TmpfsOptions {
+                size: self.shm_size,
+                mode: Some(0o1777),
+                ..Default::default()
+            },
+        )
+        .mount_in(root)?;
+
+        if self.devpts {
+            Mount::Devpts {
+                target: "/dev/pts".to_string(),
+                options: DevptsOptions {
+                    mode: Some(0o620),
+                    ptmx_mode: Some(0o666),
+                    gid: None,
+                },
+                flags: MountFlags::default(),
+            }
+            .mount_in(root)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Whether this process runs in a user namespace other than the initial one,
+/// whose uid map covers the whole range.
+fn in_user_namespace() -> bool {
+    match fs::read_to_string("/proc/self/uid_map") {
+        Ok(uid_map) => uid_map.split_whitespace().collect::<Vec<_>>() != ["0", "0", "4294967295"],
+        Err(_) => false,
+    }
+}
@@ -0,0 +1,360 @@
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+use nix::unistd::{access, geteuid, AccessFlags};
+use serde::Serialize;
+
+use crate::{
+    cgroup::{
+        cgroup_config::{CGroupConfig, CGroupHierarchy},
+        controller::Controller,
+    },
+    error::Error,
+    izolibox::BOX_ROOT_DIR,
+    sys,
+};
+
+/// Controllers needed to apply every [`CGroupOption`](crate::cgroup::cgroup_option::CGroupOption).
+const REQUIRED_CONTROLLERS: [Controller; 4] = [
+    Controller::Cpu,
+    Controller::Cpuset,
+    Controller::Memory,
+    Controller::Pids,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckStatus {
+    Pass,
+    Warn,
+    Fail,
+}
+
+impl fmt::Display for CheckStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CheckStatus::Pass => write!(f, "pass"),
+            CheckStatus::Warn => write!(f, "warn"),
+            CheckStatus::Fail => write!(f, "fail"),
+        }
+    }
+}
+
+/// The outcome of one environment check.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Check {
+    pub name: &'static str,
+    pub status: CheckStatus,
+    pub detail: String,
+}
+
+impl Check {
+    fn new(name: &'static str, status: CheckStatus, detail: impl Into<String>) -> Self {
+        Self {
+            name,
+            status,
+            detail: detail.into(),
+        }
+    }
+
+    /// A check whose failure prevents running boxes.
+    fn required(name: &'static str, ok: bool, detail: impl Into<String>) -> Self {
+        let status = if ok {
+            CheckStatus::Pass
+        } else {
+            CheckStatus::Fail
+        };
+
+        Self::new(name, status, detail)
+    }
+
+    /// A check for a feature izoli can do without.
+    fn optional(name: &'static str, ok: bool, detail: impl Into<String>) -> Self {
+        let status = if ok {
+            CheckStatus::Pass
+        } else {
+            CheckStatus::Warn
+        };
+
+        Self::new(name, status, detail)
+    }
+}
+
+/// Checks whether this host can run boxes, from kernel features down to the
+/// directories izoli writes to.
+pub fn run_checks() -> Vec<Check> {
+    let kernel = sys::kernel_version();
+
+    let mut checks = vec![check_privileges()];
+    checks.extend(check_cgroup(CGroupConfig::detect().as_ref()));
+    checks.push(check_user_namespaces());
+    checks.push(check_seccomp());
+    checks.push(check_landlock());
+    checks.push(check_kernel_version(kernel));
+    checks.push(Check::required(
+        "mount_api",
+        sys::mount_api_available(),
+        "openat2 and mount_setattr system calls, Linux 5.12",
+    ));
+    checks.push(Check::optional(
+        "cgroup_kill",
+        kernel >= Some((5, 14)),
+        "requires Linux 5.14",
+    ));
+    checks.push(Check::optional(
+        "clone3",
+        sys::clone3_available(),
+        "clone3 system call",
+    ));
+    checks.push(Check::optional(
+        "clone_into_cgroup",
+        kernel >= Some((5, 7)),
+        "requires Linux 5.7",
+    ));
+    checks.push(Check::optional(
+        "pidfd",
+        sys::pidfd_available(),
+        "pidfd_open system call",
+    ));
+    checks.push(Check::optional(
+        "loop_devices",
+        Path::new("/dev/loop-control").exists(),
+        "/dev/loop-control, needed for disk quotas",
+    ));
+    checks.push(Check::optional(
+        "time_namespace",
+        Path::new("/proc/self/ns/time").exists(),
+        "/proc/self/ns/time",
+    ));
+    checks.push(check_box_root());
+
+    checks
+}
+
+/// Formats checks as an aligned `name status detail` table.
+pub fn format_table(checks: &[Check]) -> String {
+    let width = checks
+        .iter()
+        .map(|check| check.name.len())
+        .max()
+        .unwrap_or(0);
+
+    checks
+        .iter()
+        .map(|check| {
+            format!(
+                "{:width$}  {:4}  {}\n",
+                check.name,
+                check.status,
+                check.detail,
+                width = width
+            )
+        })
+        .collect()
+}
+
+pub fn format_json(checks: &[Check]) -> String {
+    serde_json::to_string_pretty(checks).unwrap_or_default()
+}
+
+fn check_privileges() -> Check {
+    let euid = geteuid();
+
+    Check::new(
+        "privileges",
+        if euid.is_root() {
+            CheckStatus::Pass
+        } else {
+            CheckStatus::Warn
+        },
+        format!("effective uid {}", euid),
+    )
+}
+
+fn check_cgroup(config: std::result::Result<&CGroupConfig, &Error>) -> Vec<Check> {
+    if let Ok(CGroupConfig {
+        hierarchy: CGroupHierarchy::Legacy(mounts),
+        ..
+    }) = config
+    {
+        return vec![check_legacy_controllers(mounts.keys())];
+    }
+
+    let mount_point = match CGroupConfig::find_mount_point() {
+        Ok(Some(mount_point)) => mount_point,
+        Ok(None) => {
+            return vec![Check::new(
+                "cgroup2",
+                CheckStatus::Fail,
+                "no cgroup2 mount in /proc/self/mountinfo",
+            )]
+        }
+        Err(e) => return vec![Check::new("cgroup2", CheckStatus::Fail, e.to_string())],
+    };
+
+    let mut checks = vec![Check::new(
+        "cgroup2",
+        CheckStatus::Pass,
+        format!("mounted at {}", mount_point.display()),
+    )];
+
+    checks.push(check_controllers(
+        "cgroup_controllers",
+        &mount_point.join("cgroup.controllers"),
+    ));
+
+    // Controllers delegated to us are the ones available in our own cgroup,
+    // which is where the parent group is created.
+    match config {
+        Ok(config) => {
+            let own = config
+                .parent
+                .parent()
+                .map(|parent| mount_point.join(parent))
+                .unwrap_or_else(|| mount_point.clone());
+            checks.push(check_controllers(
+                "cgroup_delegation",
+                &own.join("cgroup.controllers"),
+            ));
+            checks.push(Check::required(
+                "cgroup_writable",
+                access(&own, AccessFlags::W_OK).is_ok(),
+                own.display().to_string(),
+            ));
+        }
+        Err(e) => checks.push(Check::new(
+            "cgroup_delegation",
+            CheckStatus::Warn,
+            e.to_string(),
+        )),
+    }
+
+    checks
+}
+
+fn check_controllers(name: &'static str, path: &Path) -> Check {
+    let controllers = match fs::read_to_string(path) {
+        Ok(controllers) => controllers,
+        Err(e) => {
+            return Check::new(
+                name,
+                CheckStatus::Fail,
+                format!("{}: {}", path.display(), e),
+            )
+        }
+    };
+
+    let missing: Vec<String> = REQUIRED_CONTROLLERS
+        .iter()
+        .map(|controller| controller.to_string())
+        .filter(|controller| !controllers.split_whitespace().any(|c| c == controller))
+        .collect();
+
+    if missing.is_empty() {
+        Check::new(name, CheckStatus::Pass, controllers.trim())
+    } else {
+        Check::new(
+            name,
+            CheckStatus::Warn,
+            format!("{} (missing {})", controllers.trim(), missing.join(" ")),
+        )
+    }
+}
+
+/// Boxes on cgroup v1 hosts need each controller mounted as a hierarchy.
+fn check_legacy_controllers<'a>(mounted: impl Iterator<Item = &'a String>) -> Check {
+    let mounted: Vec<&String> = mounted.collect();
+    let missing: Vec<String> = REQUIRED_CONTROLLERS
+        .iter()
+        .map(|controller| controller.to_string())
+        .filter(|controller| !mounted.contains(&controller))
+        .collect();
+    let mounted = mounted
+        .iter()
+        .map(|controller| controller.as_str())
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    if missing.is_empty() {
+        Check::new("cgroup_v1", CheckStatus::Pass, mounted)
+    } else {
+        Check::new(
+            "cgroup_v1",
+            CheckStatus::Warn,
+            format!("{} (missing {})", mounted, missing.join(" ")),
+        )
+    }
+}
+
+/// izoli runs as root and never creates a user namespace itself, so hosts
+/// restricting them are only warned about.
+fn check_user_namespaces() -> Check {
+    let name = "unprivileged_userns";
+
+    if read_sysctl("/proc/sys/user/max_user_namespaces") == Some(0) {
+        return Check::new(name, CheckStatus::Warn, "user.max_user_namespaces is 0");
+    }
+
+    if read_sysctl("/proc/sys/kernel/unprivileged_userns_clone") == Some(0) {
+        return Check::new(
+            name,
+            CheckStatus::Warn,
+            "kernel.unprivileged_userns_clone is 0",
+        );
+    }
+
+    if read_sysctl("/proc/sys/kernel/apparmor_restrict_unprivileged_userns") == Some(1) {
+        return Check::new(
+            name,
+            CheckStatus::Warn,
+            "restricted by kernel.apparmor_restrict_unprivileged_userns",
+        );
+    }
+
+    Check::new(name, CheckStatus::Pass, "allowed")
+}
+
+fn check_seccomp() -> Check {
+    match fs::read_to_string("/proc/sys/kernel/seccomp/actions_avail") {
+        Ok(actions) => Check::new("seccomp", CheckStatus::Pass, actions.trim()),
+        Err(_) => Check::new("seccomp", CheckStatus::Warn, "seccomp filters unavailable"),
+    }
+}
+
+fn check_landlock() -> Check {
+    match sys::landlock_abi_version() {
+        Some(version) => Check::new("landlock", CheckStatus::Pass, format!("ABI {}", version)),
+        None => Check::new("landlock", CheckStatus::Warn, "not supported or disabled"),
+    }
+}
+
+fn check_kernel_version(kernel: Option<(u32, u32)>) -> Check {
+    match kernel {
+        Some((major, minor)) => {
+            Check::new("kernel", CheckStatus::Pass, format!("{}.{}", major, minor))
+        }
+        None => Check::new("kernel", CheckStatus::Warn, "unknown version"),
+    }
+}
+
+/// The box root directory must be writable, or creatable below its nearest
+/// existing ancestor.
+fn check_box_root() -> Check {
+    let root = PathBuf::from(BOX_ROOT_DIR);
+    let existing = root
+        .ancestors()
+        .find(|path| path.exists())
+        .unwrap_or(Path::new("/"));
+
+    Check::required(
+        "box_root",
+        access(existing, AccessFlags::W_OK).is_ok(),
+        format!("{} (checked {})", root.display(), existing.display()),
+    )
+}
+
+fn read_sysctl(path: &str) -> Option<i64> {
+    fs::read_to_string(path).ok()?.trim().parse().ok()
+}
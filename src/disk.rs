@@ -0,0 +1,149 @@
+use std::{
+    fs::{self, OpenOptions},
+    os::fd::{AsFd, AsRawFd},
+    path::Path,
+    process::Command,
+};
+
+use nix::{
+    fcntl::AtFlags,
+    mount::MsFlags,
+    unistd::{fchownat, Gid, Uid},
+};
+use tracing::info;
+
+use crate::{
+    cgroup::units::Bytes,
+    error::{Error, Result},
+    mount::{bind_dir_in, mount_fs, open_target, MountFlags, TargetKind},
+    sys,
+};
+
+/// Caps what the box writes to disk: an ext4 image of a fixed size, created
+/// per box and loop-mounted in the box's mount namespace. It holds the
+/// upper layer of an on-disk overlay root and the writable `dirs`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiskQuota {
+    /// Size of the image, and so the block limit.
+    pub size: Bytes,
+    /// Maximum number of files and directories; `mkfs.ext4` picks one from
+    /// the size when unset.
+    pub inodes: Option<u64>,
+    /// Paths inside the box bind-mounted writable from the image, e.g.
+    /// `/home`.
+    pub dirs: Vec<String>,
+    /// Leave the image in place on teardown and reuse it the next time the
+    /// box runs, rather than starting from an empty one. A kept image must
+    /// still have `size`; `inodes` only applies when an image is created.
+    pub keep: bool,
+}
+
+impl DiskQuota {
+    /// Creates and formats the image file `image`, unless it is kept from a
+    /// previous run with the same size.
+    pub(crate) fn create_image(&self, image: &str) -> Result<()> {
+        if self.keep {
+            if let Ok(metadata) = fs::metadata(image) {
+                if metadata.len() != self.size.as_u64() {
+                    return Err(Error::DiskImageSize {
+                        path: image.into(),
+                        size: Bytes(metadata.len()),
+                        expected: self.size,
+                    });
+                }
+                return Ok(());
+            }
+        }
+        info!("creating {} disk image {}", self.size, image);
+
+        if let Some(dir) = Path::new(image).parent() {
+            fs::create_dir_all(dir).map_err(|e| Error::io(dir, e))?;
+        }
+        let _ = fs::remove_file(image);
+        let file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(image)
+            .map_err(|e| Error::io(image, e))?;
+        file.set_len(self.size.as_u64())
+            .map_err(|e| Error::io(image, e))?;
+
+        let mut mkfs = Command::new("mkfs.ext4");
+        mkfs.args(["-q", "-F", "-m", "0"]);
+        if let Some(inodes) = self.inodes {
+            mkfs.args(["-N", &inodes.to_string()]);
+        }
+        let output = mkfs
+            .arg(image)
+            .output()
+            .map_err(|e| Error::io("mkfs.ext4", e))?;
+        if !output.status.success() {
+            let _ = fs::remove_file(image);
+            return Err(Error::Command {
+                program: "mkfs.ext4",
+                output: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Attaches `image` to a loop device and mounts it on `target`. The loop
+    /// device goes away along with the mount namespace.
+    pub(crate) fn mount_image(&self, image: &str, target: &str) -> Result<()> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(image)
+            .map_err(|e| Error::io(image, e))?;
+        let (device, _device_fd) = sys::loop_attach(file.as_fd())
+            .map_err(|e| Error::syscall("ioctl", "LOOP_CONFIGURE", e))?;
+        info!("mounting {} ({}) on {}", image, device, target);
+
+        fs::create_dir_all(target).map_err(|e| Error::io(target, e))?;
+        mount_fs(
+            Some(&device),
+            target,
+            Some("ext4"),
+            MsFlags::MS_NOSUID | MsFlags::MS_NODEV,
+            None,
+        )?;
+
+        // The box only ever sees what is below `dirs` and the upper layer.
+        let dirs = format!("{}/dirs", target);
+        fs::create_dir_all(&dirs).map_err(|e| Error::io(&dirs, e))
+    }
+
+    /// Bind-mounts `dirs` from `disk`, where the image is mounted, into the
+    /// box root directory `root`, owned by the box user and group when set.
+    pub(crate) fn mount_dirs(
+        &self,
+        disk: &str,
+        root: &str,
+        uid: Option<u32>,
+        gid: Option<u32>,
+    ) -> Result<()> {
+        let flags = MountFlags {
+            no_suid: true,
+            no_dev: true,
+            ..Default::default()
+        };
+
+        for dir in &self.dirs {
+            bind_dir_in(&format!("{}/dirs", disk), dir, root, dir, flags)?;
+            if uid.is_some() || gid.is_some() {
+                let fd = open_target(root, dir, TargetKind::Directory)?;
+                fchownat(
+                    Some(fd.as_raw_fd()),
+                    "",
+                    uid.map(Uid::from_raw),
+                    gid.map(Gid::from_raw),
+                    AtFlags::AT_EMPTY_PATH,
+                )
+                .map_err(|e| Error::syscall("fchownat", dir, e))?;
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,324 @@
+use std::{fmt, io, path::PathBuf};
+
+use nix::errno::Errno;
+
+use crate::cgroup::{
+    cgroup_type::CGroupType, controller::Controller, cpuset::CpusetPartition, units::Bytes,
+};
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug)]
+pub enum Error {
+    /// Reading or writing a cgroup interface file failed.
+    CGroupIo { path: PathBuf, source: io::Error },
+    /// A cgroup interface file contained something that could not be parsed.
+    Parse { path: PathBuf, content: String },
+    /// A namespace, mount or other system call failed.
+    Syscall {
+        call: &'static str,
+        args: String,
+        errno: Errno,
+    },
+    /// The process lacks the privileges to access `path`.
+    Permission { path: PathBuf },
+    /// A filesystem operation outside the cgroup tree failed.
+    Io { path: PathBuf, source: io::Error },
+    /// `controller` is not available in the ancestor `cgroup`, so it cannot
+    /// be enabled further down the path.
+    ControllerUnavailable {
+        controller: Controller,
+        cgroup: PathBuf,
+    },
+    /// The ancestor `cgroup` has processes of its own, so controllers cannot
+    /// be enabled for its children ("no internal processes" rule).
+    InternalProcesses { cgroup: PathBuf },
+    /// `cgroup` cannot change from type `from` to `to`.
+    InvalidTypeTransition {
+        cgroup: PathBuf,
+        from: CGroupType,
+        to: CGroupType,
+    },
+    /// `feature` is not available for `cgroup` in the hierarchy it lives in,
+    /// e.g. cpuset partitions on cgroup v1.
+    Unsupported {
+        cgroup: PathBuf,
+        feature: &'static str,
+    },
+    /// `cgroup` is not a valid cpuset partition root: the kernel reported
+    /// `partition` as invalid after it was set, or an invalid state was
+    /// requested.
+    InvalidPartition {
+        cgroup: PathBuf,
+        partition: CpusetPartition,
+    },
+    /// The CPU allocator could only find `available` of the `requested` CPUs.
+    InsufficientCpus { requested: usize, available: usize },
+    /// `cgroup` did not reach `state` (e.g. frozen, or empty after a kill)
+    /// in time, such as when an ancestor is frozen or processes are stuck in
+    /// uninterruptible sleep.
+    Timeout {
+        cgroup: PathBuf,
+        state: &'static str,
+    },
+    /// The external `program` (e.g. `mkfs.ext4`) exited unsuccessfully,
+    /// printing `output`.
+    Command {
+        program: &'static str,
+        output: String,
+    },
+    /// The disk quota image `path`, kept from a previous run, is `size` rather
+    /// than the `expected` quota.
+    DiskImageSize {
+        path: PathBuf,
+        size: Bytes,
+        expected: Bytes,
+    },
+    /// The mount on `target` was refused before reaching the kernel.
+    InvalidMount { target: String, reason: String },
+    /// Collecting `path` out of a box would exceed `limit`.
+    SizeLimit { path: PathBuf, limit: Bytes },
+    /// Collecting `path` out of a box would exceed `limit` of `what`, such as
+    /// entries or directory levels.
+    CountLimit {
+        path: PathBuf,
+        limit: usize,
+        what: &'static str,
+    },
+    /// The OCI image layout at `path` is malformed or uses something izoli
+    /// does not support, such as an unknown digest algorithm.
+    Image { path: PathBuf, reason: String },
+    /// The box process failed while setting itself up, before running the
+    /// user callback.
+    Setup { step: SetupStep, errno: Errno },
+}
+
+/// The part of the in-box setup that failed, as reported by the child
+/// through the setup pipe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum SetupStep {
+    CreateRoot,
+    MountPrivate,
+    MountDisk,
+    MountRoot,
+    Dev,
+    Mount,
+    ProtectPaths,
+    Chroot,
+    Chdir,
+    SetHostname,
+    SetUser,
+}
+
+impl SetupStep {
+    pub(crate) fn from_u32(value: u32) -> Option<Self> {
+        match value {
+            0 => Some(Self::CreateRoot),
+            1 => Some(Self::MountPrivate),
+            2 => Some(Self::MountDisk),
+            3 => Some(Self::MountRoot),
+            4 => Some(Self::Dev),
+            5 => Some(Self::Mount),
+            6 => Some(Self::ProtectPaths),
+            7 => Some(Self::Chroot),
+            8 => Some(Self::Chdir),
+            9 => Some(Self::SetHostname),
+            10 => Some(Self::SetUser),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for SetupStep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SetupStep::CreateRoot => write!(f, "create root"),
+            SetupStep::MountPrivate => write!(f, "make mounts private"),
+            SetupStep::MountDisk => write!(f, "mount disk quota image"),
+            SetupStep::MountRoot => write!(f, "mount root filesystem"),
+            SetupStep::Dev => write!(f, "populate /dev"),
+            SetupStep::Mount => write!(f, "mount"),
+            SetupStep::ProtectPaths => write!(f, "mask and protect paths"),
+            SetupStep::Chroot => write!(f, "chroot"),
+            SetupStep::Chdir => write!(f, "chdir"),
+            SetupStep::SetHostname => write!(f, "set hostname"),
+            SetupStep::SetUser => write!(f, "set user"),
+        }
+    }
+}
+
+impl Error {
+    pub(crate) fn cgroup_io(path: impl Into<PathBuf>, source: io::Error) -> Self {
+        let path = path.into();
+        match source.kind() {
+            io::ErrorKind::PermissionDenied => Error::Permission { path },
+            _ => Error::CGroupIo { path, source },
+        }
+    }
+
+    pub(crate) fn io(path: impl Into<PathBuf>, source: io::Error) -> Self {
+        let path = path.into();
+        match source.kind() {
+            io::ErrorKind::PermissionDenied => Error::Permission { path },
+            _ => Error::Io { path, source },
+        }
+    }
+
+    pub(crate) fn parse(path: impl Into<PathBuf>, content: &str) -> Self {
+        Error::Parse {
+            path: path.into(),
+            content: content.to_string(),
+        }
+    }
+
+    /// Best-effort errno for reporting this error across the setup pipe.
+    pub(crate) fn errno(&self) -> Errno {
+        match self {
+            Error::CGroupIo { source, .. } | Error::Io { source, .. } => source
+                .raw_os_error()
+                .map(Errno::from_raw)
+                .unwrap_or(Errno::EIO),
+            Error::Parse { .. }
+            | Error::ControllerUnavailable { .. }
+            | Error::InvalidPartition { .. }
+            | Error::InvalidMount { .. }
+            | Error::Image { .. } => Errno::EINVAL,
+            Error::InternalProcesses { .. } => Errno::EBUSY,
+            Error::InvalidTypeTransition { .. } | Error::Unsupported { .. } => Errno::EOPNOTSUPP,
+            Error::InsufficientCpus { .. } => Errno::ENOSPC,
+            Error::Timeout { .. } => Errno::ETIMEDOUT,
+            Error::Command { .. } => Errno::EIO,
+            Error::SizeLimit { .. } => Errno::EFBIG,
+            Error::CountLimit { .. } => Errno::E2BIG,
+            Error::DiskImageSize { .. } => Errno::EEXIST,
+            Error::Syscall { errno, .. } | Error::Setup { errno, .. } => *errno,
+            Error::Permission { .. } => Errno::EACCES,
+        }
+    }
+
+    pub(crate) fn syscall(call: &'static str, args: impl Into<String>, errno: Errno) -> Self {
+        Error::Syscall {
+            call,
+            args: args.into(),
+            errno,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::CGroupIo { path, source } => {
+                write!(f, "cgroup I/O on {} failed: {}", path.display(), source)
+            }
+            Error::Parse { path, content } => {
+                write!(f, "failed to parse {}: {:?}", path.display(), content)
+            }
+            Error::Syscall { call, args, errno } => {
+                write!(f, "{}({}) failed: {}", call, args, errno)
+            }
+            Error::Permission { path } => write!(f, "permission denied: {}", path.display()),
+            Error::Io { path, source } => write!(f, "I/O on {} failed: {}", path.display(), source),
+            Error::ControllerUnavailable { controller, cgroup } => write!(
+                f,
+                "controller {} is not available in cgroup /{}",
+                controller,
+                cgroup.display()
+            ),
+            Error::InternalProcesses { cgroup } => write!(
+                f,
+                "cgroup /{} has processes of its own and cannot delegate controllers",
+                cgroup.display()
+            ),
+            Error::InvalidTypeTransition { cgroup, from, to } => write!(
+                f,
+                "cgroup /{} cannot change type from {} to {}",
+                cgroup.display(),
+                from,
+                to
+            ),
+            Error::Unsupported { cgroup, feature } => write!(
+                f,
+                "{} is not supported for cgroup /{}",
+                feature,
+                cgroup.display()
+            ),
+            Error::InvalidPartition { cgroup, partition } => write!(
+                f,
+                "cpuset partition of cgroup /{} is {}",
+                cgroup.display(),
+                partition
+            ),
+            Error::InsufficientCpus {
+                requested,
+                available,
+            } => write!(
+                f,
+                "requested {} CPUs but only {} are free",
+                requested, available
+            ),
+            Error::Timeout { cgroup, state } => write!(
+                f,
+                "timed out waiting for cgroup /{} to become {}",
+                cgroup.display(),
+                state
+            ),
+            Error::Command { program, output } => write!(f, "{} failed: {}", program, output),
+            Error::DiskImageSize {
+                path,
+                size,
+                expected,
+            } => write!(
+                f,
+                "kept disk image {} is {}, not {}; remove it to change the quota",
+                path.display(),
+                size,
+                expected
+            ),
+            Error::InvalidMount { target, reason } => {
+                write!(f, "invalid mount on {}: {}", target, reason)
+            }
+            Error::SizeLimit { path, limit } => {
+                write!(f, "{} exceeds the size limit of {}", path.display(), limit)
+            }
+            Error::CountLimit { path, limit, what } => {
+                write!(
+                    f,
+                    "{} exceeds the limit of {} {}",
+                    path.display(),
+                    limit,
+                    what
+                )
+            }
+            Error::Image { path, reason } => {
+                write!(f, "invalid image {}: {}", path.display(), reason)
+            }
+            Error::Setup { step, errno } => write!(f, "box setup failed at {}: {}", step, errno),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::CGroupIo { source, .. } | Error::Io { source, .. } => Some(source),
+            Error::Syscall { errno, .. } | Error::Setup { errno, .. } => Some(errno),
+            Error::Parse { .. }
+            | Error::Permission { .. }
+            | Error::ControllerUnavailable { .. }
+            | Error::InternalProcesses { .. }
+            | Error::InvalidPartition { .. }
+            | Error::InvalidTypeTransition { .. }
+            | Error::Unsupported { .. }
+            | Error::InsufficientCpus { .. }
+            | Error::Timeout { .. }
+            | Error::Command { .. }
+            | Error::InvalidMount { .. }
+            | Error::SizeLimit { .. }
+            | Error::CountLimit { .. }
+            | Error::DiskImageSize { .. }
+            | Error::Image { .. } => None,
+        }
+    }
+}
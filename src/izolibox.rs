@@ -1,16 +1,59 @@
-use std::{env::set_current_dir, fs, os::unix::fs::chroot, path::Path};
+use std::{
+    ffi::CString,
+    fs,
+    io::{self, ErrorKind},
+    os::fd::{AsFd, AsRawFd, OwnedFd},
+    path::Path,
+};
 
 use nix::{
-    libc::SIGCHLD,
-    mount::{mount, umount, MsFlags},
+    errno::Errno,
+    fcntl::OFlag,
+    libc::{self, SIGCHLD},
+    mount::MsFlags,
     sched::{self, CloneCb, CloneFlags},
-    unistd::{sethostname, Pid},
+    sys::{
+        signal::{kill, Signal},
+        wait::waitpid,
+    },
+    unistd::{
+        chdir, chroot, close, execvp, pipe2, read, setgid, setgroups, sethostname, setuid, write,
+        ForkResult, Gid, Pid, Uid,
+    },
+};
+use tracing::{error, info};
+
+use crate::{
+    cgroup::{
+        backend::CGroupBackend, cgroup_config::CGroupConfig, cgroup_option::CGroupOption,
+        cpu_allocator::CpuRequest, units::Bytes,
+    },
+    dev::DevOptions,
+    disk::DiskQuota,
+    error::{Error, Result, SetupStep},
+    mount::{bind_dir_in, mount_fs, umount_mount, Mount, MountFlags, OverlayDirs, TmpfsOptions},
+    oci,
+    protect::ProtectedPaths,
+    rootfs::{RootFs, UpperLayer},
+    staging::{self, BoxDir},
+    sys,
 };
-use tracing::{info, trace};
 
-use crate::cgroup::{cgroup::CGroup, cgroup_option::CGroupOption};
+/// Stack of the child started with `clone`, which runs the whole prelude
+/// and the user callback on it until it execs.
+const STACK_SIZE: usize = 1024 * 1024;
+
+/// Size limit of the built-in `/tmp`, which is charged to the box's memory.
+const DEFAULT_TMP_SIZE: Bytes = Bytes(64 * 1024 * 1024);
+
+/// Directory holding the root directory of every box.
+pub const BOX_ROOT_DIR: &str = "/var/local/lib/izoli";
+
+/// Size of a setup failure report: the failed [`SetupStep`] followed by the
+/// errno, both native-endian 32-bit integers.
+const SETUP_REPORT_SIZE: usize = 8;
 
-const STACK_SIZE: usize = 8192;
+type SetupResult = std::result::Result<(), (SetupStep, Error)>;
 
 pub struct IzoliBox {
     pub id: usize,
@@ -20,27 +63,44 @@ pub struct IzoliBox {
 #[derive(Debug, Clone, Default)]
 pub struct IzoliBoxOptions {
     pub cgroup_option: Option<CGroupOption>,
+    /// Where the box cgroup is created. Detected with
+    /// [`CGroupConfig::detect`] when unset.
+    pub cgroup_config: Option<CGroupConfig>,
+    /// CPUs to take from a shared allocator for the box's cpuset, overriding
+    /// `cgroup_option.cpus`.
+    pub cpu_request: Option<CpuRequest>,
     pub new_net: bool,
+    /// What the root directory is built from before `mounts` are added.
+    pub rootfs: RootFs,
+    /// How `/dev` is populated; the box gets no `/dev` when unset.
+    pub dev: Option<DevOptions>,
     pub mounts: Vec<Mount>,
-}
-
-#[derive(Debug, Clone, Default)]
-pub struct Mount {
-    pub target: String,
-    pub source: String,
-    pub readonly: bool,
-    pub no_exec: bool,
-}
-
-impl Mount {
-    pub fn new(target: &str, source: &str, readonly: bool, no_exec: bool) -> Self {
-        Self {
-            target: target.to_string(),
-            source: source.to_string(),
-            readonly,
-            no_exec,
-        }
-    }
+    /// Mount a read-only sysfs on `/sys`, unless `mounts` has one there.
+    pub sysfs: bool,
+    /// Masked and read-only paths, in `/proc` and `/sys` by default.
+    pub protected_paths: ProtectedPaths,
+    /// Size-limited storage for what the box writes to disk; unlimited when
+    /// unset.
+    pub disk_quota: Option<DiskQuota>,
+    /// Directory files are staged into and collected from, mounted into the
+    /// box when set.
+    pub box_dir: Option<BoxDir>,
+    /// Environment of the box process as `KEY=value` strings, replacing the
+    /// inherited one when not empty.
+    pub env: Vec<String>,
+    /// Working directory of the box process, created when missing; `/` when
+    /// unset.
+    pub workdir: Option<String>,
+    /// User the box process switches to once set up; it stays root when
+    /// unset.
+    pub uid: Option<u32>,
+    /// Group the box process switches to, also becoming its only
+    /// supplementary group. With only `uid` set, the primary group of `uid`
+    /// in the box's `/etc/passwd` is taken, and setup fails if it has none.
+    pub gid: Option<u32>,
+    /// Program and arguments run by [`IzoliBox::enter_command`], looked up in
+    /// `PATH` when the program has no `/`.
+    pub command: Vec<String>,
 }
 
 impl IzoliBox {
@@ -48,154 +108,697 @@ impl IzoliBox {
         Self { id, options }
     }
 
-    pub fn enter(&self, callback: CloneCb<'_>) -> Result<Pid, nix::errno::Errno> {
+    pub fn enter(&self, callback: CloneCb<'_>) -> Result<Pid> {
         info!("box enter");
-        let mut stack = [0u8; STACK_SIZE];
+        let result = self.inner_enter(callback);
+        if result.is_err() {
+            self.release_idle_cpus();
+        }
+        result
+    }
+
+    /// Enters the box running `command` from the options in place of a
+    /// callback.
+    pub fn enter_command(&self) -> Result<Pid> {
+        let command = self.options.command.clone();
+
+        self.enter(Box::new(move || {
+            let args = match command
+                .iter()
+                .map(|arg| CString::new(arg.as_str()))
+                .collect::<std::result::Result<Vec<_>, _>>()
+            {
+                Ok(args) if !args.is_empty() => args,
+                _ => {
+                    eprintln!("invalid command: {:?}", command);
+                    return 127;
+                }
+            };
+
+            // execvp only returns on failure.
+            let e = execvp(&args[0], &args).unwrap_err();
+            eprintln!("execvp {} failed: {}", command[0], e);
+
+            127
+        }))
+    }
+
+    fn inner_enter(&self, callback: CloneCb<'_>) -> Result<Pid> {
         let mut flags = CloneFlags::CLONE_NEWNS
             | CloneFlags::CLONE_NEWUTS
             | CloneFlags::CLONE_NEWIPC
             | CloneFlags::CLONE_NEWPID;
 
         if self.options.new_net {
-            flags = flags | CloneFlags::CLONE_NEWNET;
+            flags |= CloneFlags::CLONE_NEWNET;
         }
 
-        if let Some(cgroup_option) = &self.options.cgroup_option {
-            let cgroup = CGroup::new(&format!("izoli/box_{}", self.id)).unwrap();
-            cgroup.apply_options(cgroup_option).unwrap();
-            cgroup.enter().unwrap();
+        if let Some(disk_quota) = &self.options.disk_quota {
+            disk_quota.create_image(&self.get_disk_image())?;
         }
 
+        let cgroup = self.create_cgroup()?;
+
+        // The child reports prelude failures through this pipe. It is closed
+        // before the user callback runs (and on exec), so the parent reading
+        // EOF means setup succeeded.
+        let (setup_read, setup_write) =
+            pipe2(OFlag::O_CLOEXEC).map_err(|e| Error::syscall("pipe2", "O_CLOEXEC", e))?;
+
         let mut callback = callback;
-        let new_callback = Box::new(|| {
-            self.prelude().unwrap();
+        let pid = match &cgroup {
+            Some(cgroup) => {
+                self.spawn_into_cgroup(cgroup.as_ref(), flags, &mut callback, &setup_write)?
+            }
+            None => self.spawn(flags, &mut callback, &setup_write, None)?,
+        };
+        drop(setup_write);
+
+        if let Some((step, errno)) = Self::read_setup_error(&setup_read)? {
+            let _ = waitpid(pid, None);
+            return Err(Error::Setup { step, errno });
+        }
+
+        Ok(pid)
+    }
 
-            info!("running user code");
-            callback();
+    /// Creates the box cgroup when the box has cgroup options or asks for
+    /// CPUs from an allocator, applying the allocated CPUs as its cpuset.
+    fn create_cgroup(&self) -> Result<Option<Box<dyn CGroupBackend>>> {
+        let mut cgroup_option = match (&self.options.cgroup_option, &self.options.cpu_request) {
+            (None, None) => return Ok(None),
+            (cgroup_option, _) => cgroup_option.clone().unwrap_or_default(),
+        };
+
+        if let Some(request) = &self.options.cpu_request {
+            let cpus = request
+                .allocator
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .allocate(self.id, request.count, request.whole_cores)?;
+            cgroup_option.cpus = Some(cpus);
+        }
 
-            127
-        });
+        let config = self.get_cgroup_config()?;
+        let cgroup = config.create_backend(
+            &self.get_cgroup_name(),
+            &cgroup_option.get_required_controllers(),
+        )?;
+        cgroup.apply_options(&cgroup_option)?;
+
+        fs::create_dir_all(BOX_ROOT_DIR).map_err(|e| Error::io(BOX_ROOT_DIR, e))?;
+        let parent_file = self.get_cgroup_parent_file();
+        fs::write(&parent_file, config.get_parent_record())
+            .map_err(|e| Error::io(parent_file, e))?;
+
+        Ok(Some(cgroup))
+    }
+
+    /// Returns the allocated CPUs after a failed enter, unless box processes
+    /// may still be running on them; teardown releases them then.
+    fn release_idle_cpus(&self) {
+        let idle = match self.open_cgroup() {
+            Ok(cgroup) => cgroup.get_procs().is_ok_and(|procs| procs.is_empty()),
+            Err(Error::CGroupIo { source, .. }) => source.kind() == ErrorKind::NotFound,
+            Err(_) => false,
+        };
+        if idle {
+            self.release_cpus();
+        }
+    }
+
+    fn release_cpus(&self) {
+        if let Some(request) = &self.options.cpu_request {
+            request
+                .allocator
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .release(self.id);
+        }
+    }
+
+    /// Starts the box process directly inside `cgroup` with `clone3` and
+    /// `CLONE_INTO_CGROUP`, so the calling process never joins it. Kernels
+    /// without that support get a child that is moved into the cgroup before
+    /// it is released to run the prelude; so do boxes in cgroup v1
+    /// hierarchies, which `CLONE_INTO_CGROUP` cannot target.
+    fn spawn_into_cgroup(
+        &self,
+        cgroup: &dyn CGroupBackend,
+        flags: CloneFlags,
+        callback: &mut CloneCb<'_>,
+        setup_write: &OwnedFd,
+    ) -> Result<Pid> {
+        if let Some(cgroup_fd) = cgroup.open_fd()? {
+            match unsafe { sys::clone3(flags, Signal::SIGCHLD, Some(cgroup_fd.as_fd())) } {
+                Ok(ForkResult::Child) => {
+                    let code = self.run_child(callback, setup_write, None);
+                    unsafe { libc::_exit(code as i32) }
+                }
+                Ok(ForkResult::Parent { child }) => return Ok(child),
+                Err(Errno::ENOSYS | Errno::E2BIG | Errno::EINVAL) => {
+                    info!("clone3 with CLONE_INTO_CGROUP unavailable, falling back to clone");
+                }
+                Err(e) => {
+                    return Err(Error::syscall(
+                        "clone3",
+                        format!("{:?} | CLONE_INTO_CGROUP", flags),
+                        e,
+                    ))
+                }
+            }
+        }
+
+        // The child blocks on this pipe until it has been moved into the
+        // cgroup and the write end is closed.
+        let release =
+            pipe2(OFlag::O_CLOEXEC).map_err(|e| Error::syscall("pipe2", "O_CLOEXEC", e))?;
+        let pid = self.spawn(flags, callback, setup_write, Some(&release))?;
+        let (_, release_write) = release;
+
+        if let Err(e) = cgroup.add_procs(vec![pid.as_raw() as u32]) {
+            let _ = kill(pid, Signal::SIGKILL);
+            let _ = waitpid(pid, None);
+            return Err(e);
+        }
+        drop(release_write);
+
+        Ok(pid)
+    }
+
+    fn spawn(
+        &self,
+        flags: CloneFlags,
+        callback: &mut CloneCb<'_>,
+        setup_write: &OwnedFd,
+        release: Option<&(OwnedFd, OwnedFd)>,
+    ) -> Result<Pid> {
+        let mut stack = vec![0u8; STACK_SIZE];
+        let new_callback = Box::new(|| self.run_child(callback, setup_write, release));
 
         unsafe { sched::clone(new_callback, &mut stack, flags, Some(SIGCHLD)) }
+            .map_err(|e| Error::syscall("clone", format!("{:?}", flags), e))
+    }
+
+    /// Body of the box process: waits for release when asked to, runs the
+    /// prelude, reporting a failure through the setup pipe, then hands over
+    /// to the user callback.
+    fn run_child(
+        &self,
+        callback: &mut CloneCb<'_>,
+        setup_write: &OwnedFd,
+        release: Option<&(OwnedFd, OwnedFd)>,
+    ) -> isize {
+        if let Some((release_read, release_write)) = release {
+            let _ = close(release_write.as_raw_fd());
+            let mut buf = [0u8; 1];
+            while let Err(Errno::EINTR) | Ok(1..) = read(release_read.as_raw_fd(), &mut buf) {}
+        }
+
+        if let Err((step, e)) = self.prelude() {
+            error!("box setup failed at {}: {}", step, e);
+            Self::report_setup_error(setup_write, step, e.errno());
+            return 127;
+        }
+        let _ = close(setup_write.as_raw_fd());
+
+        info!("running user code");
+        callback();
+
+        127
+    }
+
+    fn report_setup_error(setup_write: &OwnedFd, step: SetupStep, errno: Errno) {
+        let mut report = [0u8; SETUP_REPORT_SIZE];
+        report[..4].copy_from_slice(&(step as u32).to_ne_bytes());
+        report[4..].copy_from_slice(&(errno as i32).to_ne_bytes());
+
+        let _ = write(setup_write, &report);
+    }
+
+    fn read_setup_error(setup_read: &OwnedFd) -> Result<Option<(SetupStep, Errno)>> {
+        let mut report = [0u8; SETUP_REPORT_SIZE];
+        let mut len = 0;
+
+        while len < report.len() {
+            match read(setup_read.as_raw_fd(), &mut report[len..]) {
+                Ok(0) => break,
+                Ok(n) => len += n,
+                Err(Errno::EINTR) => continue,
+                Err(e) => return Err(Error::syscall("read", "setup pipe", e)),
+            }
+        }
+
+        if len < report.len() {
+            return Ok(None);
+        }
+
+        let step = u32::from_ne_bytes(report[..4].try_into().unwrap());
+        let errno = i32::from_ne_bytes(report[4..].try_into().unwrap());
+
+        Ok(SetupStep::from_u32(step).map(|step| (step, Errno::from_raw(errno))))
+    }
+
+    /// Suspends every process in the box by freezing its cgroup.
+    pub fn pause(&self) -> Result<()> {
+        info!("box pause");
+        self.open_cgroup()?.freeze()
+    }
+
+    /// Resumes a box suspended with [`IzoliBox::pause`].
+    pub fn resume(&self) -> Result<()> {
+        info!("box resume");
+        self.open_cgroup()?.thaw()
+    }
+
+    /// Kills every process in the box, including any it has forked.
+    pub fn kill(&self) -> Result<()> {
+        info!("box kill");
+        self.open_cgroup()?.kill_all()
+    }
+
+    /// Releases what the box holds on the host once it is no longer needed:
+    /// kills anything still running, removes the box cgroup subtree, returns
+    /// allocated CPUs and discards the overlay upper layer unless it is kept.
+    pub fn teardown(&self) -> Result<()> {
+        info!("box teardown");
+
+        match self.open_cgroup() {
+            Ok(cgroup) => cgroup.remove_recursive()?,
+            Err(Error::CGroupIo { source, .. }) if source.kind() == ErrorKind::NotFound => (),
+            Err(e) => return Err(e),
+        }
+        // Only once nothing can still be running on them.
+        self.release_cpus();
+        let parent_file = self.get_cgroup_parent_file();
+        match fs::remove_file(&parent_file) {
+            Err(e) if e.kind() != ErrorKind::NotFound => return Err(Error::io(parent_file, e)),
+            _ => (),
+        }
+
+        if let RootFs::Overlay(overlay) = &self.options.rootfs {
+            if !(overlay.keep_upper && overlay.upper == UpperLayer::Disk) {
+                let overlay_dir = self.get_overlay_dir();
+                match fs::remove_dir_all(&overlay_dir) {
+                    Err(e) if e.kind() != ErrorKind::NotFound => {
+                        return Err(Error::io(overlay_dir, e))
+                    }
+                    _ => (),
+                }
+            }
+        }
+
+        if !self
+            .options
+            .box_dir
+            .as_ref()
+            .is_some_and(|box_dir| box_dir.keep)
+        {
+            self.clear_box_dir()?;
+        }
+
+        if let Some(disk_quota) = &self.options.disk_quota {
+            let _ = fs::remove_dir(self.get_disk_dir());
+            if !disk_quota.keep {
+                let image = self.get_disk_image();
+                match fs::remove_file(&image) {
+                    Err(e) if e.kind() != ErrorKind::NotFound => return Err(Error::io(image, e)),
+                    _ => (),
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Copies the host file or directory `source` into the box directory as
+    /// `dest`, a path relative to it, owned by the box user. Meant to be
+    /// called before [`IzoliBox::enter`].
+    pub fn stage(&self, source: impl AsRef<Path>, dest: &str) -> Result<()> {
+        self.get_box_dir_options()
+            .unwrap_or_else(|| self.owned(BoxDir::default()))
+            .stage(&self.get_box_dir(), source.as_ref(), dest)
+    }
+
+    /// Copies the file or directory `source`, relative to the box directory,
+    /// out to the host path `dest` once the box has exited. Symlinks leading
+    /// out of the box directory are refused, and more than `limit` bytes in
+    /// total fail the collection.
+    pub fn collect(
+        &self,
+        source: &str,
+        dest: impl AsRef<Path>,
+        limit: Option<Bytes>,
+    ) -> Result<()> {
+        staging::collect(&self.get_box_dir(), source, dest.as_ref(), limit)
+    }
+
+    /// Removes the box directory with everything staged into or written to
+    /// it.
+    pub fn clear_box_dir(&self) -> Result<()> {
+        let box_dir = self.get_box_dir();
+        match fs::remove_dir_all(&box_dir) {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(Error::io(box_dir, e)),
+            _ => Ok(()),
+        }
+    }
+
+    /// The box directory options, owned by the box user unless they name an
+    /// owner.
+    fn get_box_dir_options(&self) -> Option<BoxDir> {
+        self.options
+            .box_dir
+            .clone()
+            .map(|box_dir| self.owned(box_dir))
+    }
+
+    fn owned(&self, box_dir: BoxDir) -> BoxDir {
+        BoxDir {
+            uid: box_dir.uid.or(self.options.uid),
+            gid: box_dir.gid.or(self.options.gid),
+            ..box_dir
+        }
+    }
+
+    /// Host side of the box directory.
+    pub fn get_box_dir(&self) -> String {
+        format!("{}/{}.box", BOX_ROOT_DIR, self.id)
+    }
+
+    fn get_cgroup_config(&self) -> Result<CGroupConfig> {
+        match &self.options.cgroup_config {
+            Some(config) => Ok(config.clone()),
+            None => CGroupConfig::detect(),
+        }
     }
 
-    fn prelude(&self) -> Result<(), Box<dyn std::error::Error>> {
+    /// Opens the box cgroup under the parent group recorded when it was
+    /// created, since a process started elsewhere (another shell, a systemd
+    /// scope) detects a different parent.
+    fn open_cgroup(&self) -> Result<Box<dyn CGroupBackend>> {
+        let mut config = self.get_cgroup_config()?;
+        let parent_file = self.get_cgroup_parent_file();
+
+        match fs::read(&parent_file) {
+            Ok(record) => config.set_parent_record(&record),
+            Err(e) if e.kind() == ErrorKind::NotFound => (),
+            Err(e) => return Err(Error::io(parent_file, e)),
+        }
+
+        config.open_backend(&self.get_cgroup_name())
+    }
+
+    /// File recording the parent group of the box cgroup.
+    fn get_cgroup_parent_file(&self) -> String {
+        format!("{}/{}.cgroup", BOX_ROOT_DIR, self.id)
+    }
+
+    fn get_cgroup_name(&self) -> String {
+        format!("box_{}", self.id)
+    }
+
+    fn prelude(&self) -> SetupResult {
         info!("box prelude");
         let root = self.get_root();
         let _ = fs::remove_dir(Path::new(&root));
-        fs::create_dir_all(Path::new(&root))?;
+        fs::create_dir_all(Path::new(&root))
+            .map_err(|e| (SetupStep::CreateRoot, Error::io(&root, e)))?;
+
+        umount_mount(
+            Some("none"),
+            "/",
+            None,
+            MsFlags::MS_REC | MsFlags::MS_PRIVATE,
+            None,
+        )
+        .map_err(|e| (SetupStep::MountPrivate, e))?;
+
+        if let Some(disk_quota) = &self.options.disk_quota {
+            disk_quota
+                .mount_image(&self.get_disk_image(), &self.get_disk_dir())
+                .map_err(|e| (SetupStep::MountDisk, e))?;
+        }
+
+        self.prelude_rootfs()
+            .map_err(|e| (SetupStep::MountRoot, e))?;
+
+        if let Some(dev) = &self.options.dev {
+            dev.populate(&root).map_err(|e| (SetupStep::Dev, e))?;
+        }
 
-        self.prelude_mount()?;
+        self.prelude_mount().map_err(|e| (SetupStep::Mount, e))?;
+        self.options
+            .protected_paths
+            .apply(&root)
+            .map_err(|e| (SetupStep::ProtectPaths, e))?;
 
         info!("chroot to {}", root);
-        chroot(&root)?;
-        set_current_dir("/")?;
+        chroot(root.as_str())
+            .map_err(|e| (SetupStep::Chroot, Error::syscall("chroot", &root, e)))?;
+        let workdir = self.options.workdir.as_deref().unwrap_or("/");
+        fs::create_dir_all(workdir).map_err(|e| (SetupStep::Chdir, Error::io(workdir, e)))?;
+        chdir(workdir).map_err(|e| (SetupStep::Chdir, Error::syscall("chdir", workdir, e)))?;
+
+        sethostname("IzoliBox").map_err(|e| {
+            (
+                SetupStep::SetHostname,
+                Error::syscall("sethostname", "IzoliBox", e),
+            )
+        })?;
+
+        if !self.options.env.is_empty() {
+            for (key, _) in std::env::vars_os() {
+                std::env::remove_var(key);
+            }
+            for var in &self.options.env {
+                let (key, value) = var.split_once('=').unwrap_or((var, ""));
+                if !key.is_empty() {
+                    std::env::set_var(key, value);
+                }
+            }
+        }
+
+        self.prelude_user().map_err(|e| (SetupStep::SetUser, e))?;
+        Ok(())
+    }
+
+    /// Drops to the configured group and user, group first while the process
+    /// still may.
+    fn prelude_user(&self) -> Result<()> {
+        let uid = self.options.uid;
+        let gid = resolve_gid(uid, self.options.gid, || {
+            fs::read_to_string("/etc/passwd").map_err(|e| Error::io("/etc/passwd", e))
+        })?;
+        if uid.is_none() && gid.is_none() {
+            return Ok(());
+        }
+
+        let groups: Vec<Gid> = gid.into_iter().map(Gid::from_raw).collect();
+        setgroups(&groups).map_err(|e| Error::syscall("setgroups", format!("{:?}", gid), e))?;
+        if let Some(gid) = gid {
+            setgid(Gid::from_raw(gid)).map_err(|e| Error::syscall("setgid", gid.to_string(), e))?;
+        }
+        if let Some(uid) = uid {
+            setuid(Uid::from_raw(uid)).map_err(|e| Error::syscall("setuid", uid.to_string(), e))?;
+        }
 
-        sethostname(format!("IzoliBox"))?;
         Ok(())
     }
 
     fn get_root(&self) -> String {
-        format!("/var/local/lib/izoli/{}", self.id)
+        format!("{}/{}", BOX_ROOT_DIR, self.id)
     }
 
-    fn prelude_mount(&self) -> Result<(), Box<dyn std::error::Error>> {
-        let root = self.get_root();
+    fn get_overlay_dir(&self) -> String {
+        format!("{}/{}.overlay", BOX_ROOT_DIR, self.id)
+    }
 
-        Self::umount_mount(
-            Some("none"),
-            "/",
-            None::<&str>,
-            MsFlags::MS_REC | MsFlags::MS_PRIVATE,
-            None::<&str>,
-        )?;
+    /// Directory holding what the box wrote to its overlay root. Only
+    /// visible from the host with [`UpperLayer::Disk`] and no disk quota;
+    /// with one it is inside the image.
+    pub fn get_upper_dir(&self) -> String {
+        format!("{}/upper", self.get_layer_dir())
+    }
 
-        let mounts = [
-            ("tmp", Some("tmpfs"), MsFlags::empty()),
-            ("proc", Some("proc"), MsFlags::MS_RDONLY),
-        ];
+    /// Directory the upper and work directories of the overlay root are
+    /// created in.
+    fn get_layer_dir(&self) -> String {
+        match (&self.options.rootfs, &self.options.disk_quota) {
+            (RootFs::Overlay(overlay), Some(_)) if overlay.upper == UpperLayer::Disk => {
+                self.get_disk_dir()
+            }
+            _ => self.get_overlay_dir(),
+        }
+    }
 
-        for (target, source, flags) in mounts.iter() {
-            info!("mounting {} {:?} {:?}", target, source, flags);
-            fs::create_dir_all(format!("{}/{}", root, target))?;
-            let full_target = format!("{}/{}", root, target);
-            Self::umount_mount(
-                source.clone(),
-                &full_target,
-                source.clone(),
-                *flags,
-                None::<&str>,
-            )?;
+    /// The disk quota image file.
+    fn get_disk_image(&self) -> String {
+        format!("{}/{}.img", BOX_ROOT_DIR, self.id)
+    }
+
+    /// Where the disk quota image is mounted inside the box's mount
+    /// namespace.
+    fn get_disk_dir(&self) -> String {
+        format!("{}/{}.disk", BOX_ROOT_DIR, self.id)
+    }
+
+    /// Mounts the overlay root, if the box has one, on the root directory.
+    fn prelude_rootfs(&self) -> Result<()> {
+        let RootFs::Overlay(overlay) = &self.options.rootfs else {
+            return Ok(());
+        };
+
+        let root = self.get_root();
+        let overlay_dir = self.get_overlay_dir();
+        let upper = self.get_upper_dir();
+        let work = format!("{}/work", self.get_layer_dir());
+
+        match overlay.upper {
+            UpperLayer::Tmpfs { size } => {
+                fs::create_dir_all(&overlay_dir).map_err(|e| Error::io(&overlay_dir, e))?;
+                let data = size.map(|size| format!("size={}", size.as_u64()));
+                mount_fs(
+                    Some("tmpfs"),
+                    &overlay_dir,
+                    Some("tmpfs"),
+                    MsFlags::empty(),
+                    data.as_deref(),
+                )?;
+            }
+            UpperLayer::Disk => {
+                // Start from an empty upper layer unless the last run's is
+                // kept; the work directory is never reused.
+                if !overlay.keep_upper {
+                    let _ = fs::remove_dir_all(&upper);
+                }
+                let _ = fs::remove_dir_all(&work);
+            }
         }
 
-        for Mount {
-            target,
-            source,
-            readonly,
-            no_exec,
-        } in self.options.mounts.clone()
-        {
-            let target: &str = &target;
-            let source: &str = &source;
-            let flag_noexec = if no_exec {
-                MsFlags::MS_NOEXEC
-            } else {
-                MsFlags::empty()
-            };
+        for dir in [&upper, &work] {
+            fs::create_dir_all(dir).map_err(|e| Error::io(dir, e))?;
+        }
 
-            let flag_rdonly = if readonly {
-                MsFlags::MS_RDONLY
-            } else {
-                MsFlags::empty()
-            };
+        let (upper, work) = (Path::new(&upper), Path::new(&work));
+        info!("mounting overlay on {}", root);
+        OverlayDirs::new("/", &overlay.lower, Some(upper), Some(work))?.mount_on(&root)
+    }
 
-            let full_target: &str = &format!("{}/{}", root, target.trim_start_matches("/"));
-            info!("mounting {} to {}", source, full_target);
-            fs::create_dir_all(full_target)?;
+    /// Mounts the built-in `/tmp`, `/proc` and, if asked for, `/sys`, unless
+    /// `mounts` replaces them, followed by `mounts` in order, the box
+    /// directory and the disk quota directories.
+    fn prelude_mount(&self) -> Result<()> {
+        let root = self.get_root();
 
-            mount(
-                Some(source),
-                full_target,
-                Some("none"),
-                MsFlags::MS_BIND | MsFlags::MS_REC,
-                None::<&str>,
+        let mut builtin = vec![
+            Mount::tmpfs(
+                "/tmp",
+                TmpfsOptions {
+                    size: Some(DEFAULT_TMP_SIZE),
+                    mode: Some(0o1777),
+                    ..Default::default()
+                },
+            ),
+            Mount::Proc {
+                target: "/proc".to_string(),
+                flags: MountFlags {
+                    readonly: true,
+                    ..Default::default()
+                },
+            },
+        ];
+        if self.options.sysfs {
+            builtin.push(Mount::Sysfs {
+                target: "/sys".to_string(),
+                flags: MountFlags {
+                    readonly: true,
+                    ..Default::default()
+                },
+            });
+        }
+
+        for mount in builtin.iter().filter(|builtin| {
+            !self
+                .options
+                .mounts
+                .iter()
+                .any(|mount| Self::same_target(mount, builtin))
+        }) {
+            mount.mount_in(&root)?;
+        }
+
+        for mount in &self.options.mounts {
+            mount.mount_in(&root)?;
+        }
+
+        if let Some(box_dir) = &self.get_box_dir_options() {
+            box_dir.prepare(&self.get_box_dir())?;
+            bind_dir_in(
+                BOX_ROOT_DIR,
+                &format!("{}.box", self.id),
+                &root,
+                &box_dir.target,
+                MountFlags {
+                    no_suid: true,
+                    no_dev: true,
+                    ..Default::default()
+                },
             )?;
+        }
 
-            mount(
-                None::<&str>,
-                full_target,
-                None::<&str>,
-                MsFlags::MS_BIND
-                    | MsFlags::MS_REMOUNT
-                    | MsFlags::MS_REC
-                    | flag_rdonly
-                    | flag_noexec,
-                None::<&str>,
+        if let Some(disk_quota) = &self.options.disk_quota {
+            disk_quota.mount_dirs(
+                &self.get_disk_dir(),
+                &root,
+                self.options.uid,
+                self.options.gid,
             )?;
-            trace!(
-                "{:?}",
-                MsFlags::MS_BIND
-                    | MsFlags::MS_REMOUNT
-                    | MsFlags::MS_REC
-                    | flag_rdonly
-                    | flag_noexec
-            );
         }
 
         Ok(())
     }
 
-    fn umount_mount<P: AsRef<Path>>(
-        source: Option<&str>,
-        target: P,
-        fstype: Option<&str>,
-        flags: MsFlags,
-        data: Option<&str>,
-    ) -> Result<(), nix::Error> {
-        let target_path = target.as_ref();
+    fn same_target(a: &Mount, b: &Mount) -> bool {
+        a.get_target().trim_matches('/') == b.get_target().trim_matches('/')
+    }
+}
 
-        let _ = umount(target_path);
+/// The group a box switches to: `gid`, or the primary group of `uid` in the
+/// `passwd` database when only a user is given, so the box never keeps
+/// root's group.
+fn resolve_gid(
+    uid: Option<u32>,
+    gid: Option<u32>,
+    passwd: impl FnOnce() -> Result<String>,
+) -> Result<Option<u32>> {
+    let Some(uid) = uid.filter(|_| gid.is_none()) else {
+        return Ok(gid);
+    };
+
+    // A raw errno, as only that crosses the setup pipe.
+    oci::primary_gid(&passwd()?, uid)
+        .map(Some)
+        .ok_or_else(|| Error::io("/etc/passwd", io::Error::from_raw_os_error(libc::ENOENT)))
+}
 
-        mount(source, target_path, fstype, flags, data)
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PASSWD: &str = "root:x:0:0::/root:/bin/sh\nbox:x:1000:1001::/home/box:/bin/sh\n";
+
+    #[test]
+    fn resolve_gid_takes_the_primary_group_of_a_lone_uid() {
+        let passwd = || Ok(PASSWD.to_string());
+
+        assert_eq!(resolve_gid(Some(1000), None, passwd).unwrap(), Some(1001));
+        assert_eq!(resolve_gid(Some(1000), Some(7), passwd).unwrap(), Some(7));
+        assert_eq!(resolve_gid(None, Some(7), passwd).unwrap(), Some(7));
+        assert_eq!(resolve_gid(None, None, passwd).unwrap(), None);
+        assert!(resolve_gid(Some(4242), None, passwd).is_err());
+        assert!(resolve_gid(Some(1000), None, || {
+            Err(Error::io("/etc/passwd", ErrorKind::NotFound.into()))
+        })
+        .is_err());
     }
 }
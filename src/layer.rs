@@ -0,0 +1,516 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, Read, Seek, SeekFrom},
+    os::{
+        fd::{AsFd, AsRawFd},
+        unix::{
+            ffi::OsStrExt,
+            fs::{symlink, MetadataExt, PermissionsExt},
+        },
+    },
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use flate2::read::GzDecoder;
+use nix::{
+    errno::Errno,
+    fcntl::AtFlags,
+    libc,
+    sys::stat::{makedev, mknodat, Mode, SFlag},
+    unistd::{fchownat, geteuid, Gid, Uid},
+};
+use sha2::{Digest, Sha256};
+use tracing::{info, warn};
+
+use crate::{
+    error::{Error, Result},
+    mount::{open_target, TargetKind},
+    sys,
+};
+
+/// Prefix of the OCI whiteout entries marking deleted files.
+const WHITEOUT_PREFIX: &str = ".wh.";
+
+/// Name, after [`WHITEOUT_PREFIX`], of the entry marking its directory as
+/// opaque: nothing from the layers under it shows through.
+const OPAQUE_WHITEOUT: &str = ".wh..opq";
+
+/// Extended attribute OverlayFS reads an opaque directory from.
+pub(crate) const OPAQUE_XATTR: &str = "trusted.overlay.opaque";
+
+/// Numbers the temporary directories of the unpacks in this process.
+static NEXT_UNPACK: AtomicU64 = AtomicU64::new(0);
+
+/// Directory holding unpacked layers, one subdirectory per content digest.
+pub const LAYER_CACHE_DIR: &str = "/var/local/lib/izoli/layers";
+
+/// Something a box root filesystem layer is imported from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LayerSource {
+    /// A tar archive, plain or compressed with gzip or zstd.
+    Tar(PathBuf),
+    /// A directory tree on the host.
+    Dir(PathBuf),
+}
+
+/// An unpacked layer in the cache, to be used as a read-only lower directory
+/// of an [`OverlayRoot`](crate::rootfs::OverlayRoot).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Layer {
+    /// `sha256:` followed by the hex digest of the archive, or of the
+    /// directory tree.
+    pub digest: String,
+    pub path: PathBuf,
+}
+
+/// Layers unpacked once and shared between boxes, keyed by content digest.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LayerCache {
+    pub dir: PathBuf,
+}
+
+impl Default for LayerCache {
+    fn default() -> Self {
+        Self {
+            dir: LAYER_CACHE_DIR.into(),
+        }
+    }
+}
+
+impl LayerCache {
+    /// Returns the layer with `digest` if it has been unpacked.
+    pub fn get(&self, digest: &str) -> Option<Layer> {
+        let path = self.dir.join(digest.strip_prefix("sha256:")?);
+
+        path.is_dir().then(|| Layer {
+            digest: digest.to_string(),
+            path,
+        })
+    }
+
+    /// Unpacks `source` into the cache, unless a layer with the same content
+    /// is there already.
+    pub fn import(&self, source: &LayerSource) -> Result<Layer> {
+        let digest = match source {
+            LayerSource::Tar(path) => hash_file(path)?,
+            LayerSource::Dir(path) => hash_tree(path)?,
+        };
+
+        self.import_with_digest(&digest, |dst| match source {
+            LayerSource::Tar(path) => unpack_tar(path, dst),
+            LayerSource::Dir(path) => copy_tree(path, dst),
+        })
+    }
+
+    /// Unpacks the tar archive `path`, expected to have the content digest
+    /// `digest`, unless that layer is cached. The archive is only hashed to
+    /// check it when it has to be unpacked.
+    pub(crate) fn import_tar(&self, path: &Path, digest: &str) -> Result<Layer> {
+        self.import_with_digest(digest, |dst| {
+            let actual = hash_file(path)?;
+            if actual != digest {
+                return Err(Error::Image {
+                    path: path.into(),
+                    reason: format!("digest mismatch: expected {}, got {}", digest, actual),
+                });
+            }
+
+            unpack_tar(path, dst)
+        })
+    }
+
+    /// Returns the cached layer `digest`, unpacking it with `unpack` into a
+    /// fresh directory first if it is missing. Concurrent imports of the same
+    /// layer, from other threads or processes, each unpack into a directory
+    /// of their own, and all but the first result are dropped.
+    fn import_with_digest(
+        &self,
+        digest: &str,
+        unpack: impl FnOnce(&Path) -> Result<()>,
+    ) -> Result<Layer> {
+        if let Some(layer) = self.get(digest) {
+            info!("layer {} is cached", digest);
+            return Ok(layer);
+        }
+
+        let hex = digest.strip_prefix("sha256:").unwrap_or(digest);
+        let path = self.dir.join(hex);
+        let tmp = self.dir.join(format!(
+            ".{}.{}.{}",
+            hex,
+            process::id(),
+            NEXT_UNPACK.fetch_add(1, Ordering::Relaxed)
+        ));
+        info!("unpacking layer {} to {}", digest, path.display());
+
+        fs::create_dir_all(&self.dir).map_err(|e| Error::io(&self.dir, e))?;
+        let _ = fs::remove_dir_all(&tmp);
+        fs::create_dir(&tmp).map_err(|e| Error::io(&tmp, e))?;
+        fs::set_permissions(&tmp, fs::Permissions::from_mode(0o755))
+            .map_err(|e| Error::io(&tmp, e))?;
+
+        if let Err(e) = unpack(&tmp) {
+            let _ = fs::remove_dir_all(&tmp);
+            return Err(e);
+        }
+
+        if let Err(e) = fs::rename(&tmp, &path) {
+            let _ = fs::remove_dir_all(&tmp);
+            if !path.is_dir() {
+                return Err(Error::io(&path, e));
+            }
+        }
+
+        Ok(Layer {
+            digest: digest.to_string(),
+            path,
+        })
+    }
+}
+
+/// `sha256:<hex>` of the content of `path`.
+pub(crate) fn hash_file(path: &Path) -> Result<String> {
+    let mut file = File::open(path).map_err(|e| Error::io(path, e))?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher).map_err(|e| Error::io(path, e))?;
+
+    Ok(format_digest(hasher))
+}
+
+fn format_digest(hasher: Sha256) -> String {
+    let hex: String = hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+
+    format!("sha256:{}", hex)
+}
+
+/// Opens the tar archive `path`, decompressing it by its magic number.
+fn open_tar(path: &Path) -> Result<tar::Archive<Box<dyn Read>>> {
+    let mut file = File::open(path).map_err(|e| Error::io(path, e))?;
+    let mut magic = [0u8; 4];
+    let read = file.read(&mut magic).map_err(|e| Error::io(path, e))?;
+    file.seek(SeekFrom::Start(0))
+        .map_err(|e| Error::io(path, e))?;
+
+    let reader: Box<dyn Read> = match &magic[..read] {
+        [0x1f, 0x8b, ..] => Box::new(GzDecoder::new(BufReader::new(file))),
+        [0x28, 0xb5, 0x2f, 0xfd] => {
+            Box::new(zstd::Decoder::new(file).map_err(|e| Error::io(path, e))?)
+        }
+        _ => Box::new(BufReader::new(file)),
+    };
+
+    let mut archive = tar::Archive::new(reader);
+    archive.set_preserve_permissions(true);
+    archive.set_preserve_ownerships(geteuid().is_root());
+    archive.set_preserve_mtime(true);
+    archive.set_unpack_xattrs(false);
+    archive.set_overwrite(true);
+
+    Ok(archive)
+}
+
+/// Extracts the archive `path` into `dst`. Entries leaving `dst` through
+/// `..`, symlinks or hard links are refused by `tar`; device nodes are
+/// skipped and extended attributes, which could carry file capabilities,
+/// are dropped. OCI whiteouts become OverlayFS ones, so the layer hides what
+/// it deletes from the layers under it.
+fn unpack_tar(path: &Path, dst: &Path) -> Result<()> {
+    let mut archive = open_tar(path)?;
+
+    for entry in archive.entries().map_err(|e| Error::io(path, e))? {
+        let mut entry = entry.map_err(|e| Error::io(path, e))?;
+        let entry_type = entry.header().entry_type();
+        let entry_path = entry.path_bytes().into_owned();
+        let entry_path = String::from_utf8_lossy(&entry_path);
+
+        if let Some((parent, name)) = whiteout(&entry_path) {
+            unpack_whiteout(dst, parent, name)?;
+            continue;
+        }
+        if entry_type.is_block_special() || entry_type.is_character_special() {
+            warn!("skipping device node {}", entry_path);
+            continue;
+        }
+        if !entry.unpack_in(dst).map_err(|e| Error::io(path, e))? {
+            warn!("skipping {}: outside of the archive root", entry_path);
+        }
+    }
+
+    Ok(())
+}
+
+/// Splits an OCI whiteout entry, `dir/.wh.name`, into `dir` and `name`;
+/// `name` is [`OPAQUE_WHITEOUT`] for an opaque directory.
+fn whiteout(path: &str) -> Option<(&str, &str)> {
+    let path = path.trim_end_matches('/');
+    let (parent, file_name) = path.rsplit_once('/').unwrap_or(("", path));
+    let name = file_name.strip_prefix(WHITEOUT_PREFIX)?;
+
+    Some((parent, name))
+}
+
+/// Marks `parent/name` as deleted in the layer `dst`, with a 0/0 character
+/// device, or `parent` as opaque, with the `trusted.overlay.opaque` xattr.
+fn unpack_whiteout(dst: &Path, parent: &str, name: &str) -> Result<()> {
+    let root = dst.to_string_lossy();
+    let dir = open_target(&root, parent, TargetKind::Directory)?;
+    let path = format!("{}/{}/{}", root, parent, name);
+
+    if name == OPAQUE_WHITEOUT {
+        let dir = sys::openat2(
+            dir.as_fd(),
+            ".",
+            libc::O_RDONLY | libc::O_DIRECTORY,
+            0,
+            sys::RESOLVE_BENEATH,
+        )
+        .map_err(|e| Error::syscall("openat2", &path, e))?;
+        return sys::fsetxattr(dir.as_fd(), OPAQUE_XATTR, b"y")
+            .map_err(|e| Error::syscall("fsetxattr", &path, e));
+    }
+    if name.is_empty() || name == "." || name == ".." {
+        warn!("skipping invalid whiteout {}", path);
+        return Ok(());
+    }
+
+    match mknodat(
+        Some(dir.as_raw_fd()),
+        name,
+        SFlag::S_IFCHR,
+        Mode::empty(),
+        makedev(0, 0),
+    ) {
+        Ok(()) | Err(Errno::EEXIST) => Ok(()),
+        Err(e) => Err(Error::syscall("mknodat", &path, e)),
+    }
+}
+
+/// `sha256:<hex>` over the names, types, modes, owners and contents in the
+/// tree `root`, in name order, without following symlinks.
+fn hash_tree(root: &Path) -> Result<String> {
+    let mut hasher = Sha256::new();
+    hash_entry(root, Path::new(""), &mut hasher)?;
+
+    Ok(format_digest(hasher))
+}
+
+fn hash_entry(path: &Path, relative: &Path, hasher: &mut Sha256) -> Result<()> {
+    let metadata = fs::symlink_metadata(path).map_err(|e| Error::io(path, e))?;
+    let file_type = metadata.file_type();
+
+    hasher.update(relative.as_os_str().as_bytes());
+    hasher.update([0]);
+    hasher.update(metadata.mode().to_le_bytes());
+    hasher.update(metadata.uid().to_le_bytes());
+    hasher.update(metadata.gid().to_le_bytes());
+
+    if file_type.is_dir() {
+        for name in sorted_entries(path)? {
+            hash_entry(&path.join(&name), &relative.join(&name), hasher)?;
+        }
+    } else if file_type.is_file() {
+        hasher.update(metadata.len().to_le_bytes());
+        let mut file = File::open(path).map_err(|e| Error::io(path, e))?;
+        io::copy(&mut file, hasher).map_err(|e| Error::io(path, e))?;
+    } else if file_type.is_symlink() {
+        let target = fs::read_link(path).map_err(|e| Error::io(path, e))?;
+        hasher.update(target.as_os_str().as_bytes());
+    }
+
+    Ok(())
+}
+
+fn sorted_entries(path: &Path) -> Result<Vec<std::ffi::OsString>> {
+    let mut names = fs::read_dir(path)
+        .map_err(|e| Error::io(path, e))?
+        .map(|entry| entry.map(|entry| entry.file_name()))
+        .collect::<io::Result<Vec<_>>>()
+        .map_err(|e| Error::io(path, e))?;
+    names.sort();
+
+    Ok(names)
+}
+
+/// Copies the tree `source` into the existing directory `dst`, keeping
+/// modes and owners. Symlinks are copied as they are, device nodes and other
+/// special files are skipped.
+fn copy_tree(source: &Path, dst: &Path) -> Result<()> {
+    let metadata = fs::symlink_metadata(source).map_err(|e| Error::io(source, e))?;
+    let file_type = metadata.file_type();
+
+    if file_type.is_dir() {
+        fs::create_dir_all(dst).map_err(|e| Error::io(dst, e))?;
+        for name in sorted_entries(source)? {
+            copy_tree(&source.join(&name), &dst.join(&name))?;
+        }
+    } else if file_type.is_file() {
+        fs::copy(source, dst).map_err(|e| Error::io(source, e))?;
+    } else if file_type.is_symlink() {
+        let target = fs::read_link(source).map_err(|e| Error::io(source, e))?;
+        symlink(&target, dst).map_err(|e| Error::io(dst, e))?;
+    } else {
+        warn!("skipping special file {}", source.display());
+        return Ok(());
+    }
+
+    if geteuid().is_root() {
+        fchownat(
+            None,
+            dst,
+            Some(Uid::from_raw(metadata.uid())),
+            Some(Gid::from_raw(metadata.gid())),
+            AtFlags::AT_SYMLINK_NOFOLLOW,
+        )
+        .map_err(|e| Error::syscall("fchownat", dst.display().to_string(), e))?;
+    }
+    if !file_type.is_symlink() {
+        fs::set_permissions(dst, fs::Permissions::from_mode(metadata.mode() & 0o7777))
+            .map_err(|e| Error::io(dst, e))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use nix::unistd::getegid;
+
+    use super::*;
+
+    /// A fresh directory for one test, with `dst` to unpack into and
+    /// `outside` that nothing may reach.
+    struct Scratch(PathBuf);
+
+    impl Scratch {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("izoli-layer-{}-{}", name, process::id()));
+            let _ = fs::remove_dir_all(&path);
+            fs::create_dir_all(path.join("dst")).unwrap();
+            fs::create_dir_all(path.join("outside")).unwrap();
+            Self(path)
+        }
+
+        fn dst(&self) -> PathBuf {
+            self.0.join("dst")
+        }
+
+        fn outside(&self) -> PathBuf {
+            self.0.join("outside")
+        }
+
+        /// Writes an archive of `entries`, each a header and its content.
+        fn archive(&self, entries: Vec<(tar::Header, &[u8])>) -> PathBuf {
+            let path = self.0.join("layer.tar");
+            let mut builder = tar::Builder::new(File::create(&path).unwrap());
+            for (mut header, data) in entries {
+                header.set_size(data.len() as u64);
+                header.set_cksum();
+                builder.append(&header, data).unwrap();
+            }
+            builder.finish().unwrap();
+
+            path
+        }
+    }
+
+    impl Drop for Scratch {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// A header for `path`, written raw so paths `tar` would refuse to build
+    /// (such as `../x`) can be tested.
+    fn header(path: &str, entry_type: tar::EntryType) -> tar::Header {
+        let mut header = tar::Header::new_gnu();
+        header.as_old_mut().name[..path.len()].copy_from_slice(path.as_bytes());
+        header.set_entry_type(entry_type);
+        header.set_mode(0o644);
+        header.set_uid(geteuid().as_raw().into());
+        header.set_gid(getegid().as_raw().into());
+        header.set_mtime(0);
+        header
+    }
+
+    fn link(path: &str, entry_type: tar::EntryType, target: &Path) -> tar::Header {
+        let mut header = header(path, entry_type);
+        header.set_link_name(target).unwrap();
+        header
+    }
+
+    #[test]
+    fn whiteout_splits_parent_and_name() {
+        assert_eq!(whiteout("etc/.wh.passwd"), Some(("etc", "passwd")));
+        assert_eq!(whiteout(".wh.tmp"), Some(("", "tmp")));
+        assert_eq!(whiteout("a/b/.wh..wh..opq"), Some(("a/b", OPAQUE_WHITEOUT)));
+        assert_eq!(whiteout("a/.wh.dir/"), Some(("a", "dir")));
+        assert_eq!(whiteout("etc/passwd"), None);
+        assert_eq!(whiteout(".wh.dir/file"), None);
+    }
+
+    #[test]
+    fn unpack_skips_parent_dir_entries() {
+        let scratch = Scratch::new("dotdot");
+        let archive = scratch.archive(vec![
+            (header("../outside/x", tar::EntryType::Regular), b"x"),
+            (header("ok", tar::EntryType::Regular), b"ok"),
+        ]);
+
+        unpack_tar(&archive, &scratch.dst()).unwrap();
+
+        assert!(!scratch.outside().join("x").exists());
+        assert_eq!(fs::read(scratch.dst().join("ok")).unwrap(), b"ok");
+    }
+
+    #[test]
+    fn unpack_refuses_writing_through_symlinks() {
+        let scratch = Scratch::new("symlink");
+        let archive = scratch.archive(vec![
+            (
+                link("escape", tar::EntryType::Symlink, &scratch.outside()),
+                b"",
+            ),
+            (header("escape/pwned", tar::EntryType::Regular), b"x"),
+        ]);
+
+        assert!(unpack_tar(&archive, &scratch.dst()).is_err());
+        assert!(!scratch.outside().join("pwned").exists());
+    }
+
+    #[test]
+    fn unpack_refuses_hard_links_out_of_the_layer() {
+        let scratch = Scratch::new("hardlink");
+        for target in ["/etc/passwd", "../../../../../../etc/passwd"] {
+            let archive = scratch.archive(vec![(
+                link("passwd", tar::EntryType::Link, Path::new(target)),
+                b"",
+            )]);
+
+            assert!(unpack_tar(&archive, &scratch.dst()).is_err());
+            assert!(!scratch.dst().join("passwd").exists());
+        }
+    }
+
+    #[test]
+    fn unpack_skips_device_nodes() {
+        let scratch = Scratch::new("device");
+        let mut device = header("mem", tar::EntryType::Char);
+        device.set_device_major(1).unwrap();
+        device.set_device_minor(1).unwrap();
+        let archive = scratch.archive(vec![
+            (device, b""),
+            (header("disk", tar::EntryType::Block), b""),
+        ]);
+
+        unpack_tar(&archive, &scratch.dst()).unwrap();
+
+        assert!(fs::symlink_metadata(scratch.dst().join("mem")).is_err());
+        assert!(fs::symlink_metadata(scratch.dst().join("disk")).is_err());
+    }
+}
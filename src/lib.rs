@@ -1,2 +1,15 @@
 pub mod cgroup;
+pub mod dev;
+pub mod diagnostics;
+pub mod disk;
+pub mod error;
 pub mod izolibox;
+pub mod layer;
+pub mod mount;
+pub mod oci;
+pub mod protect;
+pub mod rootfs;
+pub mod staging;
+mod sys;
+
+pub use error::{Error, Result, SetupStep};
@@ -0,0 +1,784 @@
+use std::{
+    fmt::Write,
+    fs,
+    os::fd::{AsFd, AsRawFd, FromRawFd, OwnedFd},
+    path::{Path, PathBuf},
+};
+
+use nix::{
+    errno::Errno,
+    fcntl::{open, OFlag},
+    libc,
+    mount::{mount, umount, MsFlags},
+    sys::stat::{fstat, mkdirat, Mode},
+};
+use tracing::{info, trace};
+
+use crate::{
+    cgroup::units::Bytes,
+    error::{Error, Result},
+    sys,
+};
+
+/// Something mounted into the box root. Targets are paths inside the box.
+#[derive(Debug, Clone)]
+pub enum Mount {
+    /// A host file or directory bind-mounted into the box.
+    Bind {
+        source: String,
+        target: String,
+        flags: MountFlags,
+        /// Also bind the mounts below `source`, as `mount --rbind` does.
+        recursive: bool,
+        /// What to create at `target` when it does not exist yet.
+        target_kind: TargetKind,
+    },
+    Tmpfs {
+        target: String,
+        options: TmpfsOptions,
+        flags: MountFlags,
+    },
+    /// A proc filesystem for the box's PID namespace.
+    Proc {
+        target: String,
+        flags: MountFlags,
+    },
+    Sysfs {
+        target: String,
+        flags: MountFlags,
+    },
+    /// A devpts instance of its own, so the box cannot reach host terminals.
+    Devpts {
+        target: String,
+        options: DevptsOptions,
+        flags: MountFlags,
+    },
+    /// POSIX message queues of the box's IPC namespace.
+    Mqueue {
+        target: String,
+        flags: MountFlags,
+    },
+    /// An OverlayFS of host directories, topmost first. `upper` and its
+    /// `work` directory make it writable and must be set together; without
+    /// them it is read-only and the kernel needs at least two `lower`
+    /// directories. Paths holding `,`, `:` or `\` are refused.
+    Overlay {
+        target: String,
+        lower: Vec<PathBuf>,
+        upper: Option<PathBuf>,
+        work: Option<PathBuf>,
+        flags: MountFlags,
+    },
+}
+
+/// Per-mount flags shared by every kind of [`Mount`]. Pseudo filesystems
+/// (proc, sysfs, devpts and mqueue) are always `nosuid`, `nodev` and
+/// `noexec` on top of these, except devpts which needs device nodes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MountFlags {
+    pub readonly: bool,
+    pub no_exec: bool,
+    pub no_suid: bool,
+    pub no_dev: bool,
+    pub atime: Atime,
+    /// Propagation type set on the mount once it is in place. Left alone it
+    /// inherits that of the parent mount, private in a fresh box.
+    pub propagation: Option<Propagation>,
+}
+
+/// When access times are updated.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Atime {
+    /// Only when older than the modification time or a day old, the kernel
+    /// default.
+    #[default]
+    Relatime,
+    NoAtime,
+    /// On every access.
+    StrictAtime,
+}
+
+/// Mount propagation type, see `mount_namespaces(7)`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Propagation {
+    Private,
+    /// Receives mount events from the peer group of its source, but does not
+    /// send any back.
+    Slave,
+    Shared,
+    /// Private, and cannot be used as the source of a bind mount.
+    Unbindable,
+}
+
+/// What the target of a bind mount is created as.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TargetKind {
+    #[default]
+    Directory,
+    /// An empty file, for binding a single file such as `/etc/resolv.conf`.
+    File,
+}
+
+/// Limits and ownership of a tmpfs mount.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TmpfsOptions {
+    /// Maximum size; the kernel default is half of the RAM.
+    pub size: Option<Bytes>,
+    /// Maximum number of inodes.
+    pub nr_inodes: Option<u64>,
+    /// Permission bits of the root directory, e.g. `0o1777`.
+    pub mode: Option<u32>,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+}
+
+/// Options of a devpts mount.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DevptsOptions {
+    /// Permission bits of new terminals, e.g. `0o620`.
+    pub mode: Option<u32>,
+    /// Permission bits of the `ptmx` node, e.g. `0o666`.
+    pub ptmx_mode: Option<u32>,
+    /// Group owning new terminals, usually `tty`.
+    pub gid: Option<u32>,
+}
+
+impl Mount {
+    /// A recursive bind mount of the directory `source` on `target`.
+    pub fn bind(target: &str, source: &str, readonly: bool, no_exec: bool) -> Self {
+        Mount::Bind {
+            source: source.to_string(),
+            target: target.to_string(),
+            flags: MountFlags {
+                readonly,
+                no_exec,
+                ..Default::default()
+            },
+            recursive: true,
+            target_kind: TargetKind::Directory,
+        }
+    }
+
+    /// A bind mount of the single file `source` on `target`.
+    pub fn bind_file(target: &str, source: &str, readonly: bool) -> Self {
+        Mount::Bind {
+            source: source.to_string(),
+            target: target.to_string(),
+            flags: MountFlags {
+                readonly,
+                ..Default::default()
+            },
+            recursive: false,
+            target_kind: TargetKind::File,
+        }
+    }
+
+    /// A `nosuid`, `nodev` tmpfs on `target`.
+    pub fn tmpfs(target: &str, options: TmpfsOptions) -> Self {
+        Mount::Tmpfs {
+            target: target.to_string(),
+            options,
+            flags: MountFlags {
+                no_suid: true,
+                no_dev: true,
+                ..Default::default()
+            },
+        }
+    }
+
+    pub fn get_target(&self) -> &str {
+        match self {
+            Mount::Bind { target, .. }
+            | Mount::Tmpfs { target, .. }
+            | Mount::Proc { target, .. }
+            | Mount::Sysfs { target, .. }
+            | Mount::Devpts { target, .. }
+            | Mount::Mqueue { target, .. }
+            | Mount::Overlay { target, .. } => target,
+        }
+    }
+
+    pub fn get_flags(&self) -> &MountFlags {
+        match self {
+            Mount::Bind { flags, .. }
+            | Mount::Tmpfs { flags, .. }
+            | Mount::Proc { flags, .. }
+            | Mount::Sysfs { flags, .. }
+            | Mount::Devpts { flags, .. }
+            | Mount::Mqueue { flags, .. }
+            | Mount::Overlay { flags, .. } => flags,
+        }
+    }
+
+    /// Mounts this inside the box root directory `root`, creating the target
+    /// as needed. The target is resolved within `root` and mounted on by file
+    /// descriptor, so symlinks in the box root cannot redirect it elsewhere.
+    pub(crate) fn mount_in(&self, root: &str) -> Result<()> {
+        let target = self.get_target();
+        info!("mounting {:?} in {}", self, root);
+
+        let target_kind = match self {
+            Mount::Bind { target_kind, .. } => *target_kind,
+            _ => TargetKind::Directory,
+        };
+        let target_fd = open_target(root, target, target_kind)?;
+
+        let flags = self.get_flags();
+        let pseudo_attr = pseudo_attr();
+
+        let (mount_fd, recursive) = match self {
+            Mount::Bind {
+                source, recursive, ..
+            } => {
+                let tree = sys::open_tree(source, *recursive)
+                    .map_err(|e| Error::syscall("open_tree", source.as_str(), e))?;
+                (tree, *recursive)
+            }
+            Mount::Tmpfs { options, .. } => (
+                create_fs("tmpfs", &options.to_data(), flags.to_mount_attr())?,
+                false,
+            ),
+            Mount::Proc { .. } => (
+                create_fs("proc", "", pseudo_attr | flags.to_mount_attr())?,
+                false,
+            ),
+            Mount::Sysfs { .. } => (
+                create_fs("sysfs", "", pseudo_attr | flags.to_mount_attr())?,
+                false,
+            ),
+            Mount::Devpts { options, .. } => (
+                create_fs(
+                    "devpts",
+                    &options.to_data(),
+                    sys::MOUNT_ATTR_NOSUID | sys::MOUNT_ATTR_NOEXEC | flags.to_mount_attr(),
+                )?,
+                false,
+            ),
+            Mount::Mqueue { .. } => (
+                create_fs("mqueue", "", pseudo_attr | flags.to_mount_attr())?,
+                false,
+            ),
+            Mount::Overlay {
+                lower, upper, work, ..
+            } => {
+                let overlay = OverlayDirs::new(target, lower, upper.as_deref(), work.as_deref())?;
+
+                for dir in upper.iter().chain(work) {
+                    fs::create_dir_all(dir).map_err(|e| Error::io(dir, e))?;
+                }
+
+                let attr = flags.to_mount_attr();
+                let mount_fd = match overlay.create(attr)? {
+                    Some(mount_fd) => mount_fd,
+                    None => create_fs("overlay", &overlay.to_data(), attr)?,
+                };
+                (mount_fd, false)
+            }
+        };
+
+        sys::move_mount(mount_fd.as_fd(), target_fd.as_fd())
+            .map_err(|e| Error::syscall("move_mount", format!("{} in {}", target, root), e))?;
+
+        // A cloned tree keeps the flags of its source until changed here.
+        let mut attr = sys::MountAttr {
+            propagation: flags.propagation.map_or(0, Propagation::to_mount_attr),
+            ..Default::default()
+        };
+        if let Mount::Bind { .. } = self {
+            attr.attr_set = flags.to_mount_attr();
+            attr.attr_clr = sys::MOUNT_ATTR__ATIME;
+        }
+        if attr.attr_set != 0 || attr.attr_clr != 0 || attr.propagation != 0 {
+            trace!("{:?}", attr);
+            sys::mount_setattr(mount_fd.as_fd(), recursive, &attr)
+                .map_err(|e| Error::syscall("mount_setattr", target, e))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl MountFlags {
+    /// The `MOUNT_ATTR_*` flags of this.
+    fn to_mount_attr(self) -> u64 {
+        let mut attr = match self.atime {
+            Atime::Relatime => sys::MOUNT_ATTR_RELATIME,
+            Atime::NoAtime => sys::MOUNT_ATTR_NOATIME,
+            Atime::StrictAtime => sys::MOUNT_ATTR_STRICTATIME,
+        };
+
+        if self.readonly {
+            attr |= sys::MOUNT_ATTR_RDONLY;
+        }
+        if self.no_exec {
+            attr |= sys::MOUNT_ATTR_NOEXEC;
+        }
+        if self.no_suid {
+            attr |= sys::MOUNT_ATTR_NOSUID;
+        }
+        if self.no_dev {
+            attr |= sys::MOUNT_ATTR_NODEV;
+        }
+
+        attr
+    }
+}
+
+impl Propagation {
+    /// The `propagation` value of `struct mount_attr`.
+    fn to_mount_attr(self) -> u64 {
+        match self {
+            Propagation::Private => sys::MS_PRIVATE,
+            Propagation::Slave => sys::MS_SLAVE,
+            Propagation::Shared => sys::MS_SHARED,
+            Propagation::Unbindable => sys::MS_UNBINDABLE,
+        }
+    }
+}
+
+/// Creates a detached mount of a new `fstype` filesystem, passing it the
+/// comma-separated options `data`.
+fn create_fs(fstype: &'static str, data: &str, attr: u64) -> Result<OwnedFd> {
+    let fs_fd = sys::fsopen(fstype).map_err(|e| Error::syscall("fsopen", fstype, e))?;
+
+    let source = format!("source={}", fstype);
+    for option in [source.as_str()]
+        .into_iter()
+        .chain(data.split(',').filter(|option| !option.is_empty()))
+    {
+        let (key, value) = match option.split_once('=') {
+            Some((key, value)) => (key, Some(value)),
+            None => (option, None),
+        };
+        sys::fsconfig(fs_fd.as_fd(), key, value)
+            .map_err(|e| Error::syscall("fsconfig", format!("{}, {}", fstype, option), e))?;
+    }
+
+    sys::fsmount(fs_fd.as_fd(), attr).map_err(|e| Error::syscall("fsmount", fstype, e))
+}
+
+/// The directories of an OverlayFS: read-only `lower` ones, topmost first,
+/// and optionally a writable upper directory with its work directory.
+pub(crate) struct OverlayDirs<'a> {
+    lower: Vec<&'a str>,
+    upper: Option<(&'a str, &'a str)>,
+}
+
+impl<'a> OverlayDirs<'a> {
+    /// Checks the directories of an overlay to be mounted on `target`: at
+    /// least one lower directory, `upper` and `work` both or neither, and a
+    /// second lower directory without them. OverlayFS splits its options on
+    /// `,` and lower directory lists on `:` (with `\` escaping either), so
+    /// paths holding those are refused rather than read as further options
+    /// or layers.
+    pub(crate) fn new(
+        target: &str,
+        lower: &'a [PathBuf],
+        upper: Option<&'a Path>,
+        work: Option<&'a Path>,
+    ) -> Result<Self> {
+        let invalid = |reason: String| Error::InvalidMount {
+            target: target.to_string(),
+            reason,
+        };
+        let check = |path: &'a Path| {
+            let dir = path.to_str().ok_or_else(|| {
+                invalid(format!("overlay directory {} is not UTF-8", path.display()))
+            })?;
+            if dir.is_empty() || dir.contains([',', ':', '\\', '\n']) {
+                return Err(invalid(format!(
+                    "overlay directory {:?} is empty or contains ',', ':', '\\' or a newline",
+                    dir
+                )));
+            }
+            Ok(dir)
+        };
+
+        let upper = match (upper, work) {
+            (Some(upper), Some(work)) => Some((check(upper)?, check(work)?)),
+            (None, None) => None,
+            _ => {
+                return Err(invalid(
+                    "overlay needs both an upper and a work directory, or neither".to_string(),
+                ))
+            }
+        };
+
+        match (lower.len(), upper) {
+            (0, _) => {
+                return Err(invalid(
+                    "overlay needs at least one lower directory".to_string(),
+                ))
+            }
+            (1, None) => {
+                return Err(invalid(
+                    "read-only overlay needs at least two lower directories".to_string(),
+                ))
+            }
+            _ => (),
+        }
+
+        Ok(Self {
+            lower: lower
+                .iter()
+                .map(|lower| check(lower))
+                .collect::<Result<_>>()?,
+            upper,
+        })
+    }
+
+    /// Mount options with every lower directory in one `lowerdir` list. The
+    /// kernel caps them at a page for `mount` and 256 bytes for `fsconfig`.
+    pub(crate) fn to_data(&self) -> String {
+        let mut data = format!("lowerdir={}", self.lower.join(":"));
+        if let Some((upper, work)) = self.upper {
+            let _ = write!(data, ",upperdir={},workdir={}", upper, work);
+        }
+        data
+    }
+
+    /// Creates the overlay as a detached mount with the `MOUNT_ATTR_*` flags
+    /// `attr`, passing each lower directory on its own with `lowerdir+` so
+    /// that no option length limits the number of layers. Returns `None` on
+    /// kernels before 6.8, which lack `lowerdir+`; [`OverlayDirs::to_data`]
+    /// is the fallback there.
+    pub(crate) fn create(&self, attr: u64) -> Result<Option<OwnedFd>> {
+        if sys::kernel_version() < Some((6, 8)) {
+            return Ok(None);
+        }
+
+        let fs_fd = sys::fsopen("overlay").map_err(|e| Error::syscall("fsopen", "overlay", e))?;
+        let config = |key: &str, value: &str| {
+            sys::fsconfig(fs_fd.as_fd(), key, Some(value))
+                .map_err(|e| Error::syscall("fsconfig", format!("overlay, {}={}", key, value), e))
+        };
+
+        config("source", "overlay")?;
+        for lower in &self.lower {
+            config("lowerdir+", lower)?;
+        }
+        if let Some((upper, work)) = self.upper {
+            config("upperdir", upper)?;
+            config("workdir", work)?;
+        }
+
+        sys::fsmount(fs_fd.as_fd(), attr)
+            .map(Some)
+            .map_err(|e| Error::syscall("fsmount", "overlay", e))
+    }
+
+    /// Mounts the overlay on the host path `target`, with [`mount`] and its
+    /// one-page limit on kernels before 6.8.
+    pub(crate) fn mount_on(&self, target: &str) -> Result<()> {
+        match self.create(0)? {
+            Some(mount_fd) => {
+                let target_fd = open_root(target)?;
+                sys::move_mount(mount_fd.as_fd(), target_fd.as_fd())
+                    .map_err(|e| Error::syscall("move_mount", target, e))
+            }
+            None => mount_fs(
+                Some("overlay"),
+                target,
+                Some("overlay"),
+                MsFlags::empty(),
+                Some(&self.to_data()),
+            ),
+        }
+    }
+}
+
+/// Bind-mounts the directory `source` from inside `source_root` on `target`
+/// inside the box root directory `root`. Both are resolved like
+/// [`open_target`] and created if missing, for sources the box itself may
+/// have written to.
+pub(crate) fn bind_dir_in(
+    source_root: &str,
+    source: &str,
+    root: &str,
+    target: &str,
+    flags: MountFlags,
+) -> Result<()> {
+    let source_fd = open_target(source_root, source, TargetKind::Directory)?;
+    let target_fd = open_target(root, target, TargetKind::Directory)?;
+    info!(
+        "binding {}{} to {} in {}",
+        source_root, source, target, root
+    );
+
+    let tree = sys::open_tree_fd(source_fd.as_fd(), false)
+        .map_err(|e| Error::syscall("open_tree", source, e))?;
+    sys::move_mount(tree.as_fd(), target_fd.as_fd())
+        .map_err(|e| Error::syscall("move_mount", format!("{} in {}", target, root), e))?;
+
+    let attr = sys::MountAttr {
+        attr_set: flags.to_mount_attr(),
+        attr_clr: sys::MOUNT_ATTR__ATIME,
+        propagation: flags.propagation.map_or(0, Propagation::to_mount_attr),
+        ..Default::default()
+    };
+    sys::mount_setattr(tree.as_fd(), false, &attr)
+        .map_err(|e| Error::syscall("mount_setattr", target, e))
+}
+
+/// Hides `path` inside the box root directory `root` under an empty
+/// read-only tmpfs if it is a directory, or under `/dev/null` otherwise.
+/// Missing paths are skipped.
+pub(crate) fn mask_in(root: &str, path: &str) -> Result<()> {
+    let Some(target_fd) = open_existing(root, path)? else {
+        return Ok(());
+    };
+    let stat = fstat(target_fd.as_raw_fd()).map_err(|e| Error::syscall("fstat", path, e))?;
+
+    let mount_fd = if stat.st_mode & libc::S_IFMT == libc::S_IFDIR {
+        create_fs("tmpfs", "", sys::MOUNT_ATTR_RDONLY | pseudo_attr())?
+    } else {
+        sys::open_tree("/dev/null", false)
+            .map_err(|e| Error::syscall("open_tree", "/dev/null", e))?
+    };
+    trace!("masking {}", path);
+
+    sys::move_mount(mount_fd.as_fd(), target_fd.as_fd())
+        .map_err(|e| Error::syscall("move_mount", format!("{} in {}", path, root), e))
+}
+
+/// Makes `path` inside the box root directory `root`, and everything mounted
+/// below it, read-only by bind-mounting it onto itself. Missing paths are
+/// skipped.
+pub(crate) fn remount_readonly_in(root: &str, path: &str) -> Result<()> {
+    let Some(target_fd) = open_existing(root, path)? else {
+        return Ok(());
+    };
+    trace!("making {} read-only", path);
+
+    let tree = sys::open_tree_fd(target_fd.as_fd(), true)
+        .map_err(|e| Error::syscall("open_tree", path, e))?;
+    sys::move_mount(tree.as_fd(), target_fd.as_fd())
+        .map_err(|e| Error::syscall("move_mount", format!("{} in {}", path, root), e))?;
+
+    let attr = sys::MountAttr {
+        attr_set: sys::MOUNT_ATTR_RDONLY,
+        ..Default::default()
+    };
+    sys::mount_setattr(tree.as_fd(), true, &attr)
+        .map_err(|e| Error::syscall("mount_setattr", path, e))
+}
+
+fn pseudo_attr() -> u64 {
+    sys::MOUNT_ATTR_NOSUID | sys::MOUNT_ATTR_NODEV | sys::MOUNT_ATTR_NOEXEC
+}
+
+fn open_root(root: &str) -> Result<OwnedFd> {
+    open(
+        root,
+        OFlag::O_PATH | OFlag::O_DIRECTORY | OFlag::O_CLOEXEC,
+        Mode::empty(),
+    )
+    .map(|fd| unsafe { OwnedFd::from_raw_fd(fd) })
+    .map_err(|e| Error::syscall("open", root, e))
+}
+
+/// Opens `path` inside the box root directory `root` as an `O_PATH`
+/// descriptor if it exists, resolved like [`open_target`].
+fn open_existing(root: &str, path: &str) -> Result<Option<OwnedFd>> {
+    let root_fd = open_root(root)?;
+    let relative = path.trim_start_matches('/');
+    let relative = if relative.is_empty() { "." } else { relative };
+
+    match sys::openat2(
+        root_fd.as_fd(),
+        relative,
+        libc::O_PATH,
+        0,
+        sys::RESOLVE_IN_ROOT | sys::RESOLVE_NO_MAGICLINKS,
+    ) {
+        Ok(fd) => Ok(Some(fd)),
+        Err(Errno::ENOENT) => Ok(None),
+        Err(e) => Err(Error::syscall("openat2", format!("{}{}", root, path), e)),
+    }
+}
+
+/// Opens `path` inside the box root directory `root` as an `O_PATH`
+/// descriptor, creating missing directories and, for
+/// [`TargetKind::File`], the final component as an empty file. Every lookup
+/// is made with `RESOLVE_IN_ROOT`, so symlinks and `..` stay inside `root`.
+pub(crate) fn open_target(root: &str, path: &str, kind: TargetKind) -> Result<OwnedFd> {
+    let resolve = sys::RESOLVE_IN_ROOT | sys::RESOLVE_NO_MAGICLINKS;
+    let root_fd = open_root(root)?;
+
+    let components: Vec<&str> = path
+        .split('/')
+        .filter(|component| !component.is_empty() && *component != ".")
+        .collect();
+
+    let mut dir = sys::openat2(root_fd.as_fd(), ".", libc::O_PATH, 0, resolve)
+        .map_err(|e| Error::syscall("openat2", root, e))?;
+    let mut walked = String::new();
+
+    for (i, component) in components.iter().enumerate() {
+        let is_file = kind == TargetKind::File && i + 1 == components.len();
+        let flags = if is_file {
+            libc::O_PATH
+        } else {
+            libc::O_PATH | libc::O_DIRECTORY
+        };
+        if !walked.is_empty() {
+            walked.push('/');
+        }
+        walked.push_str(component);
+
+        let open = || sys::openat2(root_fd.as_fd(), walked.as_str(), flags, 0, resolve);
+        dir = match open() {
+            Ok(fd) => fd,
+            Err(Errno::ENOENT) => {
+                // Created right below the resolved parent; whatever is found
+                // there afterwards is resolved again from the root.
+                let (call, created) = if is_file {
+                    let created = sys::openat2(
+                        dir.as_fd(),
+                        *component,
+                        libc::O_CREAT | libc::O_EXCL | libc::O_WRONLY,
+                        0o644,
+                        sys::RESOLVE_BENEATH | sys::RESOLVE_NO_SYMLINKS,
+                    );
+                    ("openat2", created.map(drop))
+                } else {
+                    let created = mkdirat(
+                        Some(dir.as_raw_fd()),
+                        *component,
+                        Mode::from_bits_truncate(0o755),
+                    );
+                    ("mkdirat", created)
+                };
+                match created {
+                    Ok(()) | Err(Errno::EEXIST) => {}
+                    Err(e) => return Err(Error::syscall(call, format!("{}/{}", root, walked), e)),
+                }
+
+                open().map_err(|e| Error::syscall("openat2", format!("{}/{}", root, walked), e))?
+            }
+            Err(e) => return Err(Error::syscall("openat2", format!("{}/{}", root, walked), e)),
+        };
+    }
+
+    Ok(dir)
+}
+
+impl TmpfsOptions {
+    /// The tmpfs mount data, e.g. `size=67108864,mode=1777`.
+    fn to_data(self) -> String {
+        let mut data = Vec::new();
+
+        if let Some(size) = self.size {
+            data.push(format!("size={}", size.as_u64()));
+        }
+        if let Some(nr_inodes) = self.nr_inodes {
+            data.push(format!("nr_inodes={}", nr_inodes));
+        }
+        if let Some(mode) = self.mode {
+            data.push(format!("mode={:o}", mode));
+        }
+        if let Some(uid) = self.uid {
+            data.push(format!("uid={}", uid));
+        }
+        if let Some(gid) = self.gid {
+            data.push(format!("gid={}", gid));
+        }
+
+        data.join(",")
+    }
+}
+
+impl DevptsOptions {
+    fn to_data(self) -> String {
+        let mut data = vec!["newinstance".to_string()];
+
+        if let Some(mode) = self.mode {
+            data.push(format!("mode={:o}", mode));
+        }
+        if let Some(ptmx_mode) = self.ptmx_mode {
+            data.push(format!("ptmxmode={:o}", ptmx_mode));
+        }
+        if let Some(gid) = self.gid {
+            data.push(format!("gid={}", gid));
+        }
+
+        data.join(",")
+    }
+}
+
+/// Unmounts whatever a previous run may have left on `target`, then mounts.
+pub(crate) fn umount_mount(
+    source: Option<&str>,
+    target: &str,
+    fstype: Option<&str>,
+    flags: MsFlags,
+    data: Option<&str>,
+) -> Result<()> {
+    let _ = umount(target);
+
+    mount_fs(source, target, fstype, flags, data)
+}
+
+pub(crate) fn mount_fs(
+    source: Option<&str>,
+    target: &str,
+    fstype: Option<&str>,
+    flags: MsFlags,
+    data: Option<&str>,
+) -> Result<()> {
+    mount(source, target, fstype, flags, data).map_err(|e| {
+        Error::syscall(
+            "mount",
+            format!(
+                "{:?}, {}, {:?}, {:?}, {:?}",
+                source, target, fstype, flags, data
+            ),
+            e,
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn paths(paths: &[&str]) -> Vec<PathBuf> {
+        paths.iter().map(PathBuf::from).collect()
+    }
+
+    fn check(lower: &[&str], upper: Option<&str>, work: Option<&str>) -> Result<String> {
+        let lower = paths(lower);
+        OverlayDirs::new("/mnt", &lower, upper.map(Path::new), work.map(Path::new))
+            .map(|overlay| overlay.to_data())
+    }
+
+    #[test]
+    fn overlay_dirs_need_upper_and_work_together() {
+        assert_eq!(
+            check(&["/a"], Some("/u"), Some("/w")).unwrap(),
+            "lowerdir=/a,upperdir=/u,workdir=/w"
+        );
+        assert!(matches!(
+            check(&["/a", "/b"], Some("/u"), None),
+            Err(Error::InvalidMount { .. })
+        ));
+        assert!(matches!(
+            check(&["/a", "/b"], None, Some("/w")),
+            Err(Error::InvalidMount { .. })
+        ));
+    }
+
+    #[test]
+    fn read_only_overlay_dirs_need_two_lower_dirs() {
+        assert_eq!(check(&["/a", "/b"], None, None).unwrap(), "lowerdir=/a:/b");
+        assert!(check(&["/a"], None, None).is_err());
+        assert!(check(&[], Some("/u"), Some("/w")).is_err());
+    }
+
+    #[test]
+    fn overlay_dirs_refuse_separators() {
+        for dir in ["/a,b", "/a:b", "/a\\b", "/a\nb", ""] {
+            assert!(check(&[dir, "/b"], None, None).is_err(), "{:?}", dir);
+        }
+    }
+}
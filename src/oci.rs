@@ -0,0 +1,562 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::Read,
+    os::fd::AsFd,
+    path::{Path, PathBuf},
+};
+
+use nix::{errno::Errno, libc};
+use serde::{de::DeserializeOwned, Deserialize};
+use sha2::{Digest, Sha256};
+use tracing::info;
+
+use crate::{
+    error::{Error, Result},
+    izolibox::IzoliBoxOptions,
+    layer::{Layer, LayerCache, OPAQUE_XATTR},
+    rootfs::{OverlayRoot, RootFs},
+    sys,
+};
+
+/// Annotation holding the tag of a manifest in `index.json`.
+const REF_NAME_ANNOTATION: &str = "org.opencontainers.image.ref.name";
+
+/// Media types of descriptors pointing at another index rather than at a
+/// manifest.
+const INDEX_MEDIA_TYPES: [&str; 2] = [
+    "application/vnd.oci.image.index.v1+json",
+    "application/vnd.docker.distribution.manifest.list.v2+json",
+];
+
+/// An image from an OCI image layout directory (as written by e.g.
+/// `skopeo copy docker://alpine oci:alpine`), with its layers unpacked.
+#[derive(Debug, Clone)]
+pub struct OciImage {
+    /// Unpacked layers, topmost first like [`OverlayRoot::lower`].
+    pub layers: Vec<Layer>,
+    pub config: ImageConfig,
+}
+
+/// The parts of the image configuration izoli runs the image with.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ImageConfig {
+    /// `KEY=value` strings.
+    pub env: Vec<String>,
+    pub entrypoint: Vec<String>,
+    pub cmd: Vec<String>,
+    pub working_dir: Option<String>,
+    /// `user`, `uid`, `user:group` or `uid:gid`.
+    pub user: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Descriptor {
+    media_type: Option<String>,
+    digest: String,
+    #[serde(default)]
+    annotations: HashMap<String, String>,
+    platform: Option<Platform>,
+}
+
+#[derive(Deserialize)]
+struct Platform {
+    architecture: String,
+    os: String,
+}
+
+#[derive(Deserialize)]
+struct Index {
+    manifests: Vec<Descriptor>,
+}
+
+#[derive(Deserialize)]
+struct Manifest {
+    config: Descriptor,
+    layers: Vec<Descriptor>,
+}
+
+#[derive(Deserialize)]
+struct ConfigFile {
+    config: Option<RawConfig>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct RawConfig {
+    env: Option<Vec<String>>,
+    entrypoint: Option<Vec<String>>,
+    cmd: Option<Vec<String>>,
+    working_dir: Option<String>,
+    user: Option<String>,
+}
+
+impl OciImage {
+    /// Reads the image tagged `reference` from the layout `layout`, or its
+    /// only tagged image when `reference` is `None`, and unpacks the layers into
+    /// `cache`. Multi-platform images resolve to the host's platform. Every
+    /// blob is checked against its digest.
+    pub fn open(
+        layout: impl AsRef<Path>,
+        reference: Option<&str>,
+        cache: &LayerCache,
+    ) -> Result<Self> {
+        let layout = layout.as_ref();
+        let oci_layout = layout.join("oci-layout");
+        if !oci_layout.is_file() {
+            return Err(image_error(layout, "missing oci-layout"));
+        }
+
+        let index: Index = read_json(&layout.join("index.json"))?;
+        let descriptor = select_manifest(layout, &index.manifests, reference, host_architecture())?;
+
+        let manifest: Manifest = resolve_manifest(layout, descriptor)?;
+        if manifest.layers.is_empty() {
+            return Err(image_error(layout, "image has no layers"));
+        }
+        let config: ConfigFile = read_blob_json(layout, &manifest.config)?;
+
+        let mut layers = Vec::new();
+        for descriptor in &manifest.layers {
+            let blob = blob_path(layout, &descriptor.digest)?;
+            info!("applying layer {}", descriptor.digest);
+            layers.push(cache.import_tar(&blob, &descriptor.digest)?);
+        }
+        layers.reverse();
+
+        Ok(Self {
+            layers,
+            config: config.config.map(ImageConfig::from).unwrap_or_default(),
+        })
+    }
+
+    /// Entrypoint followed by the arguments of `Cmd`.
+    pub fn command(&self) -> Vec<String> {
+        let mut command = self.config.entrypoint.clone();
+        command.extend(self.config.cmd.iter().cloned());
+
+        command
+    }
+
+    /// Box options running the image: an overlay root of its layers, its
+    /// environment, working directory, user and command. Names in `User`
+    /// are looked up in the image's `/etc/passwd` and `/etc/group`.
+    pub fn options(&self) -> Result<IzoliBoxOptions> {
+        let (uid, gid) = match &self.config.user {
+            Some(user) => self.resolve_user(user)?,
+            None => (None, None),
+        };
+
+        Ok(IzoliBoxOptions {
+            rootfs: RootFs::Overlay(OverlayRoot {
+                lower: self.layers.iter().map(|layer| layer.path.clone()).collect(),
+                ..Default::default()
+            }),
+            env: self.config.env.clone(),
+            workdir: self.config.working_dir.clone(),
+            uid,
+            gid,
+            command: self.command(),
+            ..Default::default()
+        })
+    }
+
+    fn resolve_user(&self, spec: &str) -> Result<(Option<u32>, Option<u32>)> {
+        let passwd = self.read_file("etc/passwd");
+        let group = self.read_file("etc/group");
+
+        resolve_user(spec, passwd.as_deref(), group.as_deref())
+            .map(|(uid, gid)| (Some(uid), Some(gid)))
+            .ok_or_else(|| self.user_error(spec))
+    }
+
+    /// Reads `path` from the topmost layer having it, without following
+    /// symlinks out of the layer. A whiteout hides the file, and an opaque
+    /// directory above it the copies in the layers below.
+    fn read_file(&self, path: &str) -> Option<String> {
+        for layer in &self.layers {
+            let root = fs::File::open(&layer.path).ok()?;
+            let open = |path: &str, flags| {
+                sys::openat2(
+                    root.as_fd(),
+                    path,
+                    flags,
+                    0,
+                    sys::RESOLVE_BENEATH | sys::RESOLVE_NO_SYMLINKS,
+                )
+            };
+            match open(path, libc::O_RDONLY | libc::O_NONBLOCK) {
+                Ok(file) => {
+                    let mut content = String::new();
+                    fs::File::from(file).read_to_string(&mut content).ok()?;
+                    return Some(content);
+                }
+                Err(Errno::ENOENT) => {}
+                Err(_) => return None,
+            }
+
+            let opaque = ancestors(path).any(|dir| {
+                let mut value = [0u8; 1];
+                open(dir, libc::O_RDONLY | libc::O_DIRECTORY)
+                    .and_then(|dir| sys::fgetxattr(dir.as_fd(), OPAQUE_XATTR, &mut value))
+                    .is_ok_and(|len| value[..len] == *b"y")
+            });
+            if opaque {
+                return None;
+            }
+        }
+
+        None
+    }
+
+    fn user_error(&self, spec: &str) -> Error {
+        Error::Image {
+            path: self
+                .layers
+                .first()
+                .map(|layer| layer.path.clone())
+                .unwrap_or_default(),
+            reason: format!("unknown user {}", spec),
+        }
+    }
+}
+
+impl From<RawConfig> for ImageConfig {
+    fn from(config: RawConfig) -> Self {
+        Self {
+            env: config.env.unwrap_or_default(),
+            entrypoint: config.entrypoint.unwrap_or_default(),
+            cmd: config.cmd.unwrap_or_default(),
+            working_dir: config.working_dir.filter(|dir| !dir.is_empty()),
+            user: config.user.filter(|user| !user.is_empty()),
+        }
+    }
+}
+
+/// Picks the manifest tagged `reference` from the `index.json` entries
+/// `manifests`, or without `reference` the one image there, which may be
+/// listed once per platform. Several entries for `architecture` go to the
+/// first.
+fn select_manifest<'a>(
+    layout: &Path,
+    manifests: &'a [Descriptor],
+    reference: Option<&str>,
+    architecture: &str,
+) -> Result<&'a Descriptor> {
+    let tag = |descriptor: &'a Descriptor| {
+        descriptor
+            .annotations
+            .get(REF_NAME_ANNOTATION)
+            .map(String::as_str)
+    };
+
+    let candidates: Vec<&Descriptor> = match reference {
+        Some(reference) => manifests
+            .iter()
+            .filter(|descriptor| tag(descriptor) == Some(reference))
+            .collect(),
+        None => {
+            let mut tags: Vec<&str> = manifests.iter().filter_map(tag).collect();
+            tags.sort_unstable();
+            tags.dedup();
+            if tags.len() > 1 {
+                return Err(image_error(
+                    layout,
+                    format!("several images are tagged ({}); pick one", tags.join(", ")),
+                ));
+            }
+            manifests.iter().collect()
+        }
+    };
+
+    match (candidates.as_slice(), reference) {
+        ([], Some(reference)) => Err(image_error(
+            layout,
+            format!("no image tagged {}", reference),
+        )),
+        ([descriptor], _) => Ok(descriptor),
+        _ => select_platform(layout, candidates.into_iter(), architecture),
+    }
+}
+
+/// Follows `descriptor` through nested indexes down to a manifest for the
+/// host platform.
+fn resolve_manifest(layout: &Path, descriptor: &Descriptor) -> Result<Manifest> {
+    let is_index = descriptor
+        .media_type
+        .as_deref()
+        .is_some_and(|media_type| INDEX_MEDIA_TYPES.contains(&media_type));
+    if !is_index {
+        return read_blob_json(layout, descriptor);
+    }
+
+    let index: Index = read_blob_json(layout, descriptor)?;
+    let descriptor = select_platform(layout, index.manifests.iter(), host_architecture())?;
+
+    resolve_manifest(layout, descriptor)
+}
+
+/// The first descriptor for linux on `architecture`, or without a platform.
+fn select_platform<'a>(
+    layout: &Path,
+    mut descriptors: impl Iterator<Item = &'a Descriptor>,
+    architecture: &str,
+) -> Result<&'a Descriptor> {
+    descriptors
+        .find(|descriptor| {
+            descriptor.platform.as_ref().map_or(true, |platform| {
+                platform.os == "linux" && platform.architecture == architecture
+            })
+        })
+        .ok_or_else(|| image_error(layout, format!("no image for linux/{}", architecture)))
+}
+
+/// Resolves the image `User` `spec` (`user`, `uid`, `user:group` or
+/// `uid:gid`) to a uid and gid, looking names up in the `passwd` and `group`
+/// databases. Without a group, the user's primary group from `passwd` is
+/// taken, or 0 for a uid it does not list.
+fn resolve_user(spec: &str, passwd: Option<&str>, group: Option<&str>) -> Option<(u32, u32)> {
+    let (user, group_name) = match spec.split_once(':') {
+        Some((user, group)) => (user, Some(group)),
+        None => (spec, None),
+    };
+
+    let (uid, primary_gid) = match user.parse::<u32>() {
+        Ok(uid) => (uid, passwd.and_then(|passwd| primary_gid(passwd, uid))),
+        Err(_) => {
+            let fields = find_entry(passwd?, |fields| fields[0] == user)?;
+            (fields[2].parse().ok()?, fields[3].parse().ok())
+        }
+    };
+
+    let gid = match group_name {
+        Some(name) => match name.parse::<u32>() {
+            Ok(gid) => gid,
+            Err(_) => find_entry(group?, |fields| fields[0] == name)?[2]
+                .parse()
+                .ok()?,
+        },
+        None => primary_gid.unwrap_or(0),
+    };
+
+    Some((uid, gid))
+}
+
+/// The primary group of `uid` in the `passwd` database.
+pub(crate) fn primary_gid(passwd: &str, uid: u32) -> Option<u32> {
+    let uid = uid.to_string();
+
+    find_entry(passwd, |fields| fields[2] == uid)?[3]
+        .parse()
+        .ok()
+}
+
+/// The fields of the first entry of the colon-separated database `content`
+/// (`/etc/passwd` or `/etc/group`) that `matches`.
+fn find_entry(content: &str, matches: impl Fn(&[&str]) -> bool) -> Option<Vec<&str>> {
+    content
+        .lines()
+        .map(|line| line.split(':').collect::<Vec<_>>())
+        .find(|fields| fields.len() > 3 && matches(fields))
+}
+
+/// The directories `path` is in, e.g. `etc` and `etc/ssl` for
+/// `etc/ssl/cert.pem`.
+fn ancestors(path: &str) -> impl Iterator<Item = &str> {
+    path.match_indices('/').map(move |(i, _)| &path[..i])
+}
+
+/// The host architecture as named by OCI (after Go's `GOARCH`).
+fn host_architecture() -> &'static str {
+    match std::env::consts::ARCH {
+        "x86_64" => "amd64",
+        "x86" => "386",
+        "aarch64" => "arm64",
+        "powerpc64" if cfg!(target_endian = "little") => "ppc64le",
+        arch => arch,
+    }
+}
+
+/// Path of the blob `digest`, refusing anything but a well-formed sha256
+/// digest so it cannot point outside of `blobs`.
+fn blob_path(layout: &Path, digest: &str) -> Result<PathBuf> {
+    match digest.split_once(':') {
+        Some(("sha256", hex))
+            if hex.len() == 64 && hex.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f')) =>
+        {
+            Ok(layout.join("blobs/sha256").join(hex))
+        }
+        _ => Err(image_error(
+            layout,
+            format!("unsupported digest {}", digest),
+        )),
+    }
+}
+
+fn read_blob_json<T: DeserializeOwned>(layout: &Path, descriptor: &Descriptor) -> Result<T> {
+    let path = blob_path(layout, &descriptor.digest)?;
+    let content = fs::read(&path).map_err(|e| Error::io(&path, e))?;
+
+    let hex: String = Sha256::digest(&content)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    if descriptor.digest != format!("sha256:{}", hex) {
+        return Err(image_error(
+            &path,
+            format!("digest mismatch: expected {}", descriptor.digest),
+        ));
+    }
+
+    parse_json(&path, &content)
+}
+
+fn read_json<T: DeserializeOwned>(path: &Path) -> Result<T> {
+    let content = fs::read(path).map_err(|e| Error::io(path, e))?;
+
+    parse_json(path, &content)
+}
+
+fn parse_json<T: DeserializeOwned>(path: &Path, content: &[u8]) -> Result<T> {
+    serde_json::from_slice(content).map_err(|e| image_error(path, e.to_string()))
+}
+
+fn image_error(path: &Path, reason: impl Into<String>) -> Error {
+    Error::Image {
+        path: path.into(),
+        reason: reason.into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PASSWD: &str = "root:x:0:0:root:/root:/bin/sh\n\
+                          daemon:x:2:2:daemon:/sbin:/sbin/nologin\n\
+                          app:x:1000:1001::/home/app:/bin/sh\n";
+    const GROUP: &str = "root:x:0:root\nwheel:x:10:root\nstaff:x:50:\n";
+
+    fn digest(n: u8) -> String {
+        format!("sha256:{:064x}", n)
+    }
+
+    /// `index.json` entries for `(tag, architecture)` pairs.
+    fn manifests(entries: &[(Option<&str>, Option<&str>)]) -> Vec<Descriptor> {
+        let manifests: Vec<serde_json::Value> = entries
+            .iter()
+            .enumerate()
+            .map(|(i, (tag, architecture))| {
+                let mut descriptor = serde_json::json!({ "digest": digest(i as u8) });
+                if let Some(tag) = tag {
+                    descriptor["annotations"] = serde_json::json!({ REF_NAME_ANNOTATION: tag });
+                }
+                if let Some(architecture) = architecture {
+                    descriptor["platform"] =
+                        serde_json::json!({ "os": "linux", "architecture": architecture });
+                }
+                descriptor
+            })
+            .collect();
+
+        serde_json::from_value(serde_json::Value::Array(manifests)).unwrap()
+    }
+
+    fn select(
+        manifests: &[Descriptor],
+        reference: Option<&str>,
+        architecture: &str,
+    ) -> Result<String> {
+        select_manifest(Path::new("img"), manifests, reference, architecture)
+            .map(|descriptor| descriptor.digest.clone())
+    }
+
+    #[test]
+    fn blob_path_accepts_only_sha256_digests() {
+        let hex = "a".repeat(64);
+        assert_eq!(
+            blob_path(Path::new("img"), &format!("sha256:{}", hex)).unwrap(),
+            Path::new("img/blobs/sha256").join(&hex)
+        );
+
+        for digest in [
+            format!("sha512:{}", hex),
+            format!("sha256:{}", &hex[1..]),
+            format!("sha256:{}", hex.to_uppercase()),
+            format!("sha256:../../{}", &hex[6..]),
+            format!("sha256:{}/x", &hex[2..]),
+            hex.clone(),
+            String::new(),
+        ] {
+            assert!(blob_path(Path::new("img"), &digest).is_err(), "{}", digest);
+        }
+    }
+
+    #[test]
+    fn select_manifest_by_tag() {
+        let index = manifests(&[(Some("1.0"), None), (Some("latest"), None)]);
+        assert_eq!(select(&index, Some("latest"), "amd64").unwrap(), digest(1));
+        assert!(select(&index, Some("2.0"), "amd64").is_err());
+    }
+
+    #[test]
+    fn select_manifest_without_tag_needs_a_single_image() {
+        let index = manifests(&[(Some("1.0"), None), (Some("latest"), None)]);
+        assert!(matches!(
+            select(&index, None, "amd64"),
+            Err(Error::Image { reason, .. }) if reason.contains("1.0, latest")
+        ));
+
+        let index = manifests(&[(Some("latest"), None)]);
+        assert_eq!(select(&index, None, "amd64").unwrap(), digest(0));
+        let index = manifests(&[(None, None)]);
+        assert_eq!(select(&index, None, "amd64").unwrap(), digest(0));
+    }
+
+    #[test]
+    fn select_manifest_by_platform() {
+        let index = manifests(&[
+            (Some("latest"), Some("arm64")),
+            (Some("latest"), Some("amd64")),
+            (Some("latest"), Some("amd64")),
+        ]);
+        assert_eq!(select(&index, None, "amd64").unwrap(), digest(1));
+        assert_eq!(select(&index, Some("latest"), "arm64").unwrap(), digest(0));
+        assert!(select(&index, None, "riscv64").is_err());
+
+        let index = manifests(&[(None, Some("arm64")), (None, None)]);
+        assert_eq!(select(&index, None, "amd64").unwrap(), digest(1));
+    }
+
+    #[test]
+    fn resolve_user_names_and_ids() {
+        let resolve = |spec| resolve_user(spec, Some(PASSWD), Some(GROUP));
+
+        assert_eq!(resolve("root"), Some((0, 0)));
+        assert_eq!(resolve("app"), Some((1000, 1001)));
+        assert_eq!(resolve("app:wheel"), Some((1000, 10)));
+        assert_eq!(resolve("daemon:50"), Some((2, 50)));
+        assert_eq!(resolve("2:staff"), Some((2, 50)));
+        assert_eq!(resolve("4242:4343"), Some((4242, 4343)));
+        assert_eq!(resolve("nobody"), None);
+        assert_eq!(resolve("app:nogroup"), None);
+    }
+
+    #[test]
+    fn resolve_user_takes_the_primary_group_of_a_uid() {
+        assert_eq!(resolve_user("1000", Some(PASSWD), None), Some((1000, 1001)));
+        assert_eq!(resolve_user("4242", Some(PASSWD), None), Some((4242, 0)));
+        assert_eq!(resolve_user("1000", None, None), Some((1000, 0)));
+        assert_eq!(resolve_user("app", None, None), None);
+    }
+
+    #[test]
+    fn ancestors_lists_parent_directories() {
+        assert_eq!(
+            ancestors("etc/ssl/cert.pem").collect::<Vec<_>>(),
+            ["etc", "etc/ssl"]
+        );
+        assert_eq!(ancestors("passwd").count(), 0);
+    }
+}
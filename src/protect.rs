@@ -0,0 +1,68 @@
+use crate::{
+    error::Result,
+    mount::{mask_in, remount_readonly_in},
+};
+
+/// Paths the box must not see or write, applied once every mount is in
+/// place. The defaults are those of OCI runtimes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProtectedPaths {
+    /// Hidden under an empty read-only tmpfs (directories) or `/dev/null`
+    /// (files).
+    pub masked: Vec<String>,
+    /// Made read-only along with everything mounted below them.
+    pub readonly: Vec<String>,
+}
+
+impl Default for ProtectedPaths {
+    fn default() -> Self {
+        let masked = [
+            "/proc/acpi",
+            "/proc/asound",
+            "/proc/kcore",
+            "/proc/keys",
+            "/proc/latency_stats",
+            "/proc/timer_list",
+            "/proc/timer_stats",
+            "/proc/sched_debug",
+            "/proc/scsi",
+            "/sys/firmware",
+            "/sys/devices/virtual/powercap",
+        ];
+        let readonly = [
+            "/proc/bus",
+            "/proc/fs",
+            "/proc/irq",
+            "/proc/sys",
+            "/proc/sysrq-trigger",
+        ];
+
+        Self {
+            masked: masked.map(String::from).to_vec(),
+            readonly: readonly.map(String::from).to_vec(),
+        }
+    }
+}
+
+impl ProtectedPaths {
+    /// No masked or read-only paths.
+    pub fn none() -> Self {
+        Self {
+            masked: Vec::new(),
+            readonly: Vec::new(),
+        }
+    }
+
+    /// Applies this in the box root directory `root`. Paths the box does not
+    /// have are skipped.
+    pub(crate) fn apply(&self, root: &str) -> Result<()> {
+        for path in &self.readonly {
+            remount_readonly_in(root, path)?;
+        }
+        for path in &self.masked {
+            mask_in(root, path)?;
+        }
+
+        Ok(())
+    }
+}
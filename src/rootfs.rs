@@ -0,0 +1,48 @@
+use std::path::PathBuf;
+
+use crate::cgroup::units::Bytes;
+
+/// What the box root directory is built from before `mounts` are placed in
+/// it.
+#[derive(Debug, Clone, Default)]
+pub enum RootFs {
+    /// An empty directory; only the bind mounts are visible.
+    #[default]
+    Empty,
+    /// An overlay of read-only directories with a writable upper layer of its
+    /// own.
+    Overlay(OverlayRoot),
+}
+
+/// An OverlayFS root: `lower` directories stacked read-only under a per-box
+/// upper layer receiving every write.
+#[derive(Debug, Clone, Default)]
+pub struct OverlayRoot {
+    /// Read-only layers, topmost first. Paths must be absolute and must not
+    /// contain `:` or `,`. Archives and directory trees are turned into
+    /// shared layers by [`LayerCache`](crate::layer::LayerCache).
+    pub lower: Vec<PathBuf>,
+    pub upper: UpperLayer,
+    /// Leave the upper layer in place on teardown so what the box wrote can
+    /// be inspected, and reuse it the next time the box runs. Only takes
+    /// effect for [`UpperLayer::Disk`]; with a disk quota the layer lives in
+    /// the quota image, which must be kept as well.
+    pub keep_upper: bool,
+}
+
+/// Where the upper and work directories of an [`OverlayRoot`] live.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UpperLayer {
+    /// A tmpfs private to the box's mount namespace, gone once the box exits.
+    /// `size` caps what the box can write, defaulting to half of the RAM.
+    Tmpfs { size: Option<Bytes> },
+    /// A directory in the box state directory on the host filesystem, removed
+    /// on teardown unless kept.
+    Disk,
+}
+
+impl Default for UpperLayer {
+    fn default() -> Self {
+        UpperLayer::Tmpfs { size: None }
+    }
+}
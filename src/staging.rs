@@ -0,0 +1,492 @@
+use std::{
+    ffi::{CString, OsStr},
+    fs::{self, File},
+    io::{self, Read},
+    os::{
+        fd::{AsFd, AsRawFd, BorrowedFd, IntoRawFd, OwnedFd},
+        unix::{ffi::OsStrExt, fs::PermissionsExt},
+    },
+    path::{Path, PathBuf},
+};
+
+use nix::{
+    dir::Dir,
+    errno::Errno,
+    fcntl::AtFlags,
+    libc,
+    sys::stat::{fchmodat, fstat, fstatat, mkdirat, FchmodatFlags, Mode},
+    unistd::{fchownat, symlinkat, unlinkat, Gid, Uid, UnlinkatFlags},
+};
+use tracing::{info, warn};
+
+use crate::{
+    cgroup::units::Bytes,
+    error::{Error, Result},
+    mount::{open_target, TargetKind},
+    sys,
+};
+
+/// Lookups below a directory that must not leave it nor follow symlinks.
+const RESOLVE_STRICT: u64 = sys::RESOLVE_BENEATH | sys::RESOLVE_NO_SYMLINKS;
+
+/// A host directory bind-mounted writable into the box, which files are
+/// staged into before the box starts and collected from after it exits.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BoxDir {
+    /// Where the box sees it.
+    pub target: String,
+    /// Owner given to the directory and to staged files, so the box can
+    /// write them. Defaults to the user and group the box runs as
+    /// ([`IzoliBoxOptions::uid`](crate::izolibox::IzoliBoxOptions::uid) and
+    /// `gid`), or root.
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    /// Leave the directory in place on teardown, to collect from it later.
+    pub keep: bool,
+}
+
+impl Default for BoxDir {
+    fn default() -> Self {
+        Self {
+            target: "/box".to_string(),
+            uid: None,
+            gid: None,
+            keep: false,
+        }
+    }
+}
+
+impl BoxDir {
+    /// Creates the host directory `path` and hands it, with whatever was
+    /// staged or left in it before, to the box user.
+    pub(crate) fn prepare(&self, path: &str) -> Result<()> {
+        fs::create_dir_all(path).map_err(|e| Error::io(path, e))?;
+        self.chown_tree(path)
+    }
+
+    /// Changes the owner of `path` and everything below it, without
+    /// following symlinks or leaving `path`. Like [`collect`], it gives up
+    /// past [`MAX_ENTRIES`] entries or [`MAX_DEPTH`] directory levels.
+    fn chown_tree(&self, path: &str) -> Result<()> {
+        let (uid, gid) = self.owner();
+        let root = open_target(path, "", TargetKind::Directory)?;
+        fchownat(
+            Some(root.as_raw_fd()),
+            "",
+            Some(uid),
+            Some(gid),
+            AtFlags::AT_EMPTY_PATH,
+        )
+        .map_err(|e| Error::syscall("fchownat", path, e))?;
+
+        let mut dirs = vec![(root, PathBuf::from(path), 0)];
+        let mut entries = 1;
+
+        while let Some((fd, dir_path, depth)) = dirs.pop() {
+            let mut dir = sys::reopen(fd.as_fd(), libc::O_RDONLY | libc::O_DIRECTORY)
+                .and_then(|readable| Dir::from_fd(readable.into_raw_fd()))
+                .map_err(|e| Error::syscall("open", dir_path.display().to_string(), e))?;
+            let names: Vec<CString> = dir
+                .iter()
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.file_name().to_owned())
+                .filter(|name| name.as_bytes() != b"." && name.as_bytes() != b"..")
+                .collect();
+
+            for name in names {
+                let child_path = dir_path.join(OsStr::from_bytes(name.as_bytes()));
+                entries += 1;
+                if entries > MAX_ENTRIES {
+                    return Err(Error::CountLimit {
+                        path: child_path,
+                        limit: MAX_ENTRIES,
+                        what: "entries",
+                    });
+                }
+
+                let syscall = |call, e| Error::syscall(call, child_path.display().to_string(), e);
+                let stat = fstatat(
+                    Some(fd.as_raw_fd()),
+                    name.as_c_str(),
+                    AtFlags::AT_SYMLINK_NOFOLLOW,
+                )
+                .map_err(|e| syscall("fstatat", e))?;
+                if stat.st_uid != uid.as_raw() || stat.st_gid != gid.as_raw() {
+                    fchownat(
+                        Some(fd.as_raw_fd()),
+                        name.as_c_str(),
+                        Some(uid),
+                        Some(gid),
+                        AtFlags::AT_SYMLINK_NOFOLLOW,
+                    )
+                    .map_err(|e| syscall("fchownat", e))?;
+                }
+
+                if stat.st_mode & libc::S_IFMT == libc::S_IFDIR {
+                    if depth + 1 >= MAX_DEPTH {
+                        return Err(Error::CountLimit {
+                            path: child_path,
+                            limit: MAX_DEPTH,
+                            what: "directory levels",
+                        });
+                    }
+                    let child = sys::openat2(
+                        fd.as_fd(),
+                        name.as_c_str(),
+                        libc::O_PATH | libc::O_DIRECTORY,
+                        0,
+                        RESOLVE_STRICT,
+                    )
+                    .map_err(|e| syscall("openat2", e))?;
+                    dirs.push((child, child_path, depth + 1));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn owner(&self) -> (Uid, Gid) {
+        (
+            Uid::from_raw(self.uid.unwrap_or(0)),
+            Gid::from_raw(self.gid.unwrap_or(0)),
+        )
+    }
+
+    /// Copies the host file or directory `source` to `dest` inside the host
+    /// directory `path`. Symlinks are copied as they are; what already
+    /// exists at `dest` is resolved without leaving `path`.
+    pub(crate) fn stage(&self, path: &str, source: &Path, dest: &str) -> Result<()> {
+        self.prepare(path)?;
+
+        let dest = dest.trim_end_matches('/');
+        let (parent, name) = dest.rsplit_once('/').unwrap_or(("", dest));
+        if name.is_empty() || name == "." || name == ".." {
+            return Err(Error::io(
+                dest,
+                io::Error::from(io::ErrorKind::InvalidInput),
+            ));
+        }
+        info!("staging {} to {}/{}", source.display(), path, dest);
+
+        let parent = open_target(path, parent, TargetKind::Directory)?;
+        self.copy_in(source, parent.as_fd(), name)
+    }
+
+    fn copy_in(&self, source: &Path, dir: BorrowedFd<'_>, name: &str) -> Result<()> {
+        let metadata = fs::symlink_metadata(source).map_err(|e| Error::io(source, e))?;
+        let file_type = metadata.file_type();
+        // Never hand set-user-ID or set-group-ID files to the box.
+        let mode = metadata.permissions().mode() & 0o777;
+
+        if file_type.is_dir() {
+            match mkdirat(Some(dir.as_raw_fd()), name, Mode::from_bits_truncate(mode)) {
+                Ok(()) | Err(Errno::EEXIST) => {}
+                Err(e) => return Err(Error::syscall("mkdirat", name, e)),
+            }
+            let child = sys::openat2(
+                dir,
+                name,
+                libc::O_PATH | libc::O_DIRECTORY,
+                0,
+                RESOLVE_STRICT,
+            )
+            .map_err(|e| Error::syscall("openat2", name, e))?;
+            // mkdirat applies the umask.
+            fchmodat(
+                Some(dir.as_raw_fd()),
+                name,
+                Mode::from_bits_truncate(mode),
+                FchmodatFlags::FollowSymlink,
+            )
+            .map_err(|e| Error::syscall("fchmodat", name, e))?;
+
+            for entry in fs::read_dir(source).map_err(|e| Error::io(source, e))? {
+                let entry = entry.map_err(|e| Error::io(source, e))?;
+                let Some(entry_name) = entry.file_name().to_str().map(String::from) else {
+                    warn!("skipping {}: name is not UTF-8", entry.path().display());
+                    continue;
+                };
+                self.copy_in(&entry.path(), child.as_fd(), &entry_name)?;
+            }
+        } else if file_type.is_file() {
+            // Truncated only once known to be a regular file: opening a FIFO
+            // the box left there must not block, nor a device be written.
+            let target = sys::openat2(
+                dir,
+                name,
+                libc::O_WRONLY
+                    | libc::O_CREAT
+                    | libc::O_NOFOLLOW
+                    | libc::O_NONBLOCK
+                    | libc::O_NOCTTY,
+                mode,
+                RESOLVE_STRICT,
+            )
+            .map_err(|e| Error::syscall("openat2", name, e))?;
+            let mut target = File::from(target);
+            if !target.metadata().map_err(|e| Error::io(name, e))?.is_file() {
+                return Err(Error::io(
+                    name,
+                    io::Error::from(io::ErrorKind::InvalidInput),
+                ));
+            }
+            target.set_len(0).map_err(|e| Error::io(name, e))?;
+            let mut file = File::open(source).map_err(|e| Error::io(source, e))?;
+            io::copy(&mut file, &mut target).map_err(|e| Error::io(source, e))?;
+            // The mode passed to open is subject to the umask.
+            target
+                .set_permissions(fs::Permissions::from_mode(mode))
+                .map_err(|e| Error::io(source, e))?;
+        } else if file_type.is_symlink() {
+            let link = fs::read_link(source).map_err(|e| Error::io(source, e))?;
+            let _ = unlinkat(Some(dir.as_raw_fd()), name, UnlinkatFlags::NoRemoveDir);
+            symlinkat(&link, Some(dir.as_raw_fd()), name)
+                .map_err(|e| Error::syscall("symlinkat", name, e))?;
+        } else {
+            warn!(
+                "skipping {}: not a file, directory or symlink",
+                source.display()
+            );
+            return Ok(());
+        }
+
+        let (uid, gid) = self.owner();
+        fchownat(
+            Some(dir.as_raw_fd()),
+            name,
+            Some(uid),
+            Some(gid),
+            AtFlags::AT_SYMLINK_NOFOLLOW,
+        )
+        .map_err(|e| Error::syscall("fchownat", name, e))
+    }
+}
+
+/// Deepest directory nesting [`collect`] and ownership changes descend into.
+const MAX_DEPTH: usize = 64;
+/// Most files and directories [`collect`] copies out of one path, or whose
+/// owner is changed in a box directory.
+const MAX_ENTRIES: usize = 100_000;
+
+/// Copies the file or directory `source` inside the host directory `path` to
+/// the host path `dest`, refusing paths that leave `path` through symlinks or
+/// `..`. Symlinks and special files within a directory are skipped. Fails
+/// once more than `limit` bytes, [`MAX_ENTRIES`] entries or
+/// [`MAX_DEPTH`] directory levels would be copied.
+pub(crate) fn collect(path: &str, source: &str, dest: &Path, limit: Option<Bytes>) -> Result<()> {
+    info!("collecting {}/{} to {}", path, source, dest.display());
+    let root = open_target(path, "", TargetKind::Directory)?;
+    let relative = source.trim_start_matches('/');
+    let relative = if relative.is_empty() { "." } else { relative };
+
+    // Only O_PATH until the type is known: opening a device node the box
+    // left behind could have side effects on the host.
+    let fd = sys::openat2(
+        root.as_fd(),
+        relative,
+        libc::O_PATH,
+        0,
+        sys::RESOLVE_BENEATH | sys::RESOLVE_NO_MAGICLINKS,
+    )
+    .map_err(|e| Error::syscall("openat2", format!("{}/{}", path, source), e))?;
+
+    Collector {
+        remaining: limit.map(Bytes::as_u64),
+        limit,
+        entries: 0,
+    }
+    .copy_out(fd, dest, 0)
+}
+
+/// What is left of the limits of one [`collect`] call.
+struct Collector {
+    remaining: Option<u64>,
+    limit: Option<Bytes>,
+    entries: usize,
+}
+
+impl Collector {
+    fn copy_out(&mut self, fd: OwnedFd, dest: &Path, depth: usize) -> Result<()> {
+        self.entries += 1;
+        if self.entries > MAX_ENTRIES {
+            return Err(Error::CountLimit {
+                path: dest.to_path_buf(),
+                limit: MAX_ENTRIES,
+                what: "entries",
+            });
+        }
+
+        let stat = fstat(fd.as_raw_fd())
+            .map_err(|e| Error::syscall("fstat", dest.display().to_string(), e))?;
+        let mode = stat.st_mode & 0o777;
+
+        match stat.st_mode & libc::S_IFMT {
+            libc::S_IFDIR => {
+                if depth >= MAX_DEPTH {
+                    return Err(Error::CountLimit {
+                        path: dest.to_path_buf(),
+                        limit: MAX_DEPTH,
+                        what: "directory levels",
+                    });
+                }
+                fs::create_dir_all(dest).map_err(|e| Error::io(dest, e))?;
+
+                // Dir needs a readable descriptor, `fd` stays the one looked
+                // up from.
+                let mut dir = sys::reopen(fd.as_fd(), libc::O_RDONLY | libc::O_DIRECTORY)
+                    .and_then(|readable| Dir::from_fd(readable.into_raw_fd()))
+                    .map_err(|e| Error::syscall("open", dest.display().to_string(), e))?;
+                let names: Vec<String> = dir
+                    .iter()
+                    .filter_map(|entry| entry.ok())
+                    .filter_map(|entry| entry.file_name().to_str().ok().map(String::from))
+                    .filter(|name| name != "." && name != "..")
+                    .collect();
+
+                for name in names {
+                    let dest = dest.join(&name);
+                    let child = sys::openat2(
+                        fd.as_fd(),
+                        name.as_str(),
+                        libc::O_PATH | libc::O_NOFOLLOW,
+                        0,
+                        RESOLVE_STRICT,
+                    );
+                    match child {
+                        Ok(child) => self.copy_out(child, &dest, depth + 1)?,
+                        Err(Errno::ELOOP) => warn!("skipping symlink {}", name),
+                        Err(e) => return Err(Error::syscall("openat2", name, e)),
+                    }
+                }
+
+                fs::set_permissions(dest, fs::Permissions::from_mode(mode))
+                    .map_err(|e| Error::io(dest, e))
+            }
+            libc::S_IFREG => {
+                let file = sys::reopen(fd.as_fd(), libc::O_RDONLY | libc::O_NOCTTY)
+                    .map(File::from)
+                    .map_err(|e| Error::syscall("open", dest.display().to_string(), e))?;
+                let mut out = File::create(dest).map_err(|e| Error::io(dest, e))?;
+
+                let copied = match self.remaining {
+                    // Reading one byte more than allowed tells a file that
+                    // fits exactly from one that does not.
+                    Some(left) => io::copy(&mut file.take(left + 1), &mut out),
+                    None => io::copy(&mut &file, &mut out),
+                }
+                .map_err(|e| Error::io(dest, e))?;
+
+                if let (Some(left), Some(limit)) = (self.remaining.as_mut(), self.limit) {
+                    if copied > *left {
+                        drop(out);
+                        let _ = fs::remove_file(dest);
+                        return Err(Error::SizeLimit {
+                            path: dest.to_path_buf(),
+                            limit,
+                        });
+                    }
+                    *left -= copied;
+                }
+
+                fs::set_permissions(dest, fs::Permissions::from_mode(mode))
+                    .map_err(|e| Error::io(dest, e))
+            }
+            libc::S_IFLNK => {
+                warn!("skipping symlink {}", dest.display());
+                Ok(())
+            }
+            _ => {
+                warn!("skipping {}: not a file or directory", dest.display());
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process;
+
+    use nix::unistd::{getegid, geteuid, mkfifo};
+
+    use super::*;
+
+    /// A fresh box directory for one test, staged and owned as the current
+    /// user.
+    struct Scratch(PathBuf);
+
+    impl Scratch {
+        fn new(name: &str) -> Self {
+            let path =
+                std::env::temp_dir().join(format!("izoli-staging-{}-{}", name, process::id()));
+            let _ = fs::remove_dir_all(&path);
+            fs::create_dir_all(path.join("box")).unwrap();
+            Self(path)
+        }
+
+        fn box_dir(&self) -> String {
+            self.0.join("box").to_str().unwrap().to_string()
+        }
+    }
+
+    impl Drop for Scratch {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn box_dir() -> BoxDir {
+        BoxDir {
+            uid: Some(geteuid().as_raw()),
+            gid: Some(getegid().as_raw()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn stage_refuses_a_fifo_at_the_target() {
+        let scratch = Scratch::new("fifo");
+        let source = scratch.0.join("input.txt");
+        fs::write(&source, "data").unwrap();
+        mkfifo(
+            &scratch.0.join("box/input.txt"),
+            Mode::from_bits_truncate(0o644),
+        )
+        .unwrap();
+
+        assert!(box_dir()
+            .stage(&scratch.box_dir(), &source, "input.txt")
+            .is_err());
+
+        fs::remove_file(scratch.0.join("box/input.txt")).unwrap();
+        box_dir()
+            .stage(&scratch.box_dir(), &source, "input.txt")
+            .unwrap();
+        assert_eq!(
+            fs::read_to_string(scratch.0.join("box/input.txt")).unwrap(),
+            "data"
+        );
+    }
+
+    #[test]
+    fn prepare_stops_at_the_depth_limit() {
+        let scratch = Scratch::new("depth");
+        let mut deep = scratch.0.join("box");
+        for _ in 0..MAX_DEPTH {
+            deep.push("d");
+        }
+        fs::create_dir_all(&deep).unwrap();
+
+        assert!(matches!(
+            box_dir().prepare(&scratch.box_dir()),
+            Err(Error::CountLimit {
+                limit: MAX_DEPTH,
+                ..
+            })
+        ));
+
+        fs::remove_dir(&deep).unwrap();
+        box_dir().prepare(&scratch.box_dir()).unwrap();
+    }
+}
@@ -0,0 +1,480 @@
+//! Thin wrappers around system calls that nix does not provide.
+
+use std::{
+    ffi::CString,
+    os::fd::{AsRawFd, BorrowedFd, FromRawFd, OwnedFd},
+};
+
+use nix::{
+    errno::Errno,
+    fcntl::{self, OFlag},
+    libc::{self, SYS_clone3},
+    sched::CloneFlags,
+    sys::{signal::Signal, stat::Mode},
+    unistd::{ForkResult, Pid},
+    NixPath,
+};
+
+/// Place the child into the cgroup referred to by `clone_args.cgroup`.
+const CLONE_INTO_CGROUP: u64 = 0x200000000;
+
+/// `struct clone_args` as of Linux 5.7, the first version with `cgroup`.
+#[repr(C)]
+#[derive(Debug, Default)]
+struct CloneArgs {
+    flags: u64,
+    pidfd: u64,
+    child_tid: u64,
+    parent_tid: u64,
+    exit_signal: u64,
+    stack: u64,
+    stack_size: u64,
+    tls: u64,
+    set_tid: u64,
+    set_tid_size: u64,
+    cgroup: u64,
+}
+
+/// Forks with `clone3`, starting the child directly in the cgroup `cgroup`
+/// when given. Like `fork`, the child continues on a copy of the caller's
+/// stack.
+///
+/// # Safety
+///
+/// The same restrictions as for `fork` apply: in a multi-threaded program
+/// the child may only call async-signal-safe functions.
+pub(crate) unsafe fn clone3(
+    flags: CloneFlags,
+    exit_signal: Signal,
+    cgroup: Option<BorrowedFd<'_>>,
+) -> nix::Result<ForkResult> {
+    let mut args = CloneArgs {
+        flags: flags.bits() as u32 as u64,
+        exit_signal: exit_signal as u64,
+        ..Default::default()
+    };
+
+    if let Some(cgroup) = cgroup {
+        args.flags |= CLONE_INTO_CGROUP;
+        args.cgroup = cgroup.as_raw_fd() as u64;
+    }
+
+    let res = libc::syscall(
+        SYS_clone3,
+        &mut args as *mut CloneArgs,
+        std::mem::size_of::<CloneArgs>(),
+    );
+
+    match Errno::result(res)? {
+        0 => Ok(ForkResult::Child),
+        pid => Ok(ForkResult::Parent {
+            child: Pid::from_raw(pid as libc::pid_t),
+        }),
+    }
+}
+
+/// `struct open_how` of `openat2`.
+#[repr(C)]
+#[derive(Debug, Default)]
+struct OpenHow {
+    flags: u64,
+    mode: u64,
+    resolve: u64,
+}
+
+/// Resolve the path as if `dirfd` were the root directory, including
+/// absolute symlinks and `..`.
+pub(crate) const RESOLVE_IN_ROOT: u64 = 0x10;
+/// Reject `/proc/<pid>/fd`-style magic links.
+pub(crate) const RESOLVE_NO_MAGICLINKS: u64 = 0x02;
+/// Reject any symlink.
+pub(crate) const RESOLVE_NO_SYMLINKS: u64 = 0x04;
+/// Reject paths leaving `dirfd`.
+pub(crate) const RESOLVE_BENEATH: u64 = 0x08;
+
+/// Opens `path` relative to `dirfd` with `openat2`, confined by the
+/// `resolve` flags.
+pub(crate) fn openat2<P: ?Sized + NixPath>(
+    dirfd: BorrowedFd<'_>,
+    path: &P,
+    flags: libc::c_int,
+    mode: libc::mode_t,
+    resolve: u64,
+) -> nix::Result<OwnedFd> {
+    let how = OpenHow {
+        flags: (flags | libc::O_CLOEXEC) as u64,
+        mode: mode as u64,
+        resolve,
+    };
+
+    let res = path.with_nix_path(|path| unsafe {
+        libc::syscall(
+            libc::SYS_openat2,
+            dirfd.as_raw_fd(),
+            path.as_ptr(),
+            &how as *const OpenHow,
+            std::mem::size_of::<OpenHow>(),
+        )
+    })?;
+
+    Errno::result(res).map(|fd| unsafe { OwnedFd::from_raw_fd(fd as libc::c_int) })
+}
+
+/// Opens the file `fd` refers to, typically an `O_PATH` descriptor, again
+/// with `flags`, through `/proc/self/fd` so it is the same file.
+pub(crate) fn reopen(fd: BorrowedFd<'_>, flags: libc::c_int) -> nix::Result<OwnedFd> {
+    let path = format!("/proc/self/fd/{}", fd.as_raw_fd());
+    let path = CString::new(path).map_err(|_| Errno::EINVAL)?;
+    let res = unsafe { libc::open(path.as_ptr(), flags | libc::O_CLOEXEC) };
+
+    Errno::result(res).map(|fd| unsafe { OwnedFd::from_raw_fd(fd) })
+}
+
+/// The path passed along with `AT_EMPTY_PATH` and the `*_EMPTY_PATH` flags,
+/// which make a call act on the file descriptor itself.
+const EMPTY_PATH: *const libc::c_char = b"\0".as_ptr().cast();
+/// Attach a copy of the tree instead of opening it in place.
+const OPEN_TREE_CLONE: libc::c_uint = 1;
+const MOVE_MOUNT_F_EMPTY_PATH: libc::c_uint = 0x04;
+const MOVE_MOUNT_T_EMPTY_PATH: libc::c_uint = 0x40;
+const FSOPEN_CLOEXEC: libc::c_uint = 0x01;
+const FSMOUNT_CLOEXEC: libc::c_uint = 0x01;
+const FSCONFIG_SET_FLAG: libc::c_uint = 0;
+const FSCONFIG_SET_STRING: libc::c_uint = 1;
+const FSCONFIG_CMD_CREATE: libc::c_uint = 6;
+
+pub(crate) const MOUNT_ATTR_RDONLY: u64 = 0x01;
+pub(crate) const MOUNT_ATTR_NOSUID: u64 = 0x02;
+pub(crate) const MOUNT_ATTR_NODEV: u64 = 0x04;
+pub(crate) const MOUNT_ATTR_NOEXEC: u64 = 0x08;
+/// Mask of the atime settings below.
+pub(crate) const MOUNT_ATTR__ATIME: u64 = 0x70;
+pub(crate) const MOUNT_ATTR_RELATIME: u64 = 0x00;
+pub(crate) const MOUNT_ATTR_NOATIME: u64 = 0x10;
+pub(crate) const MOUNT_ATTR_STRICTATIME: u64 = 0x20;
+
+/// Propagation types, as the `MS_*` mount flags of the same name.
+pub(crate) const MS_UNBINDABLE: u64 = 1 << 17;
+pub(crate) const MS_PRIVATE: u64 = 1 << 18;
+pub(crate) const MS_SLAVE: u64 = 1 << 19;
+pub(crate) const MS_SHARED: u64 = 1 << 20;
+
+/// `struct mount_attr` of `mount_setattr`, without `userns_fd` use.
+#[repr(C)]
+#[derive(Debug, Default)]
+pub(crate) struct MountAttr {
+    pub(crate) attr_set: u64,
+    pub(crate) attr_clr: u64,
+    /// One of `MS_PRIVATE`, `MS_SLAVE`, `MS_SHARED` or `MS_UNBINDABLE`, or 0
+    /// to leave it unchanged.
+    pub(crate) propagation: u64,
+    pub(crate) userns_fd: u64,
+}
+
+/// Clones the mount tree at the host path `path` (only its top mount unless
+/// `recursive`) into a detached mount.
+pub(crate) fn open_tree(path: &str, recursive: bool) -> nix::Result<OwnedFd> {
+    let path = CString::new(path).map_err(|_| Errno::EINVAL)?;
+    let mut flags = OPEN_TREE_CLONE | libc::O_CLOEXEC as libc::c_uint;
+    if recursive {
+        flags |= libc::AT_RECURSIVE as libc::c_uint;
+    }
+
+    let res = unsafe { libc::syscall(libc::SYS_open_tree, libc::AT_FDCWD, path.as_ptr(), flags) };
+
+    Errno::result(res).map(|fd| unsafe { OwnedFd::from_raw_fd(fd as libc::c_int) })
+}
+
+/// Like [`open_tree`], for the file or directory `fd` refers to.
+pub(crate) fn open_tree_fd(fd: BorrowedFd<'_>, recursive: bool) -> nix::Result<OwnedFd> {
+    let mut flags =
+        OPEN_TREE_CLONE | libc::O_CLOEXEC as libc::c_uint | libc::AT_EMPTY_PATH as libc::c_uint;
+    if recursive {
+        flags |= libc::AT_RECURSIVE as libc::c_uint;
+    }
+
+    let res = unsafe { libc::syscall(libc::SYS_open_tree, fd.as_raw_fd(), EMPTY_PATH, flags) };
+
+    Errno::result(res).map(|fd| unsafe { OwnedFd::from_raw_fd(fd as libc::c_int) })
+}
+
+/// Attaches the detached mount `from` on top of the file or directory `to`.
+pub(crate) fn move_mount(from: BorrowedFd<'_>, to: BorrowedFd<'_>) -> nix::Result<()> {
+    let res = unsafe {
+        libc::syscall(
+            libc::SYS_move_mount,
+            from.as_raw_fd(),
+            EMPTY_PATH,
+            to.as_raw_fd(),
+            EMPTY_PATH,
+            MOVE_MOUNT_F_EMPTY_PATH | MOVE_MOUNT_T_EMPTY_PATH,
+        )
+    };
+
+    Errno::result(res).map(drop)
+}
+
+/// Changes the flags and propagation of the mount `fd`, and of every mount
+/// below it if `recursive`.
+pub(crate) fn mount_setattr(
+    fd: BorrowedFd<'_>,
+    recursive: bool,
+    attr: &MountAttr,
+) -> nix::Result<()> {
+    let mut flags = libc::AT_EMPTY_PATH as libc::c_uint;
+    if recursive {
+        flags |= libc::AT_RECURSIVE as libc::c_uint;
+    }
+
+    let res = unsafe {
+        libc::syscall(
+            libc::SYS_mount_setattr,
+            fd.as_raw_fd(),
+            EMPTY_PATH,
+            flags,
+            attr as *const MountAttr,
+            std::mem::size_of::<MountAttr>(),
+        )
+    };
+
+    Errno::result(res).map(drop)
+}
+
+/// Opens a filesystem context for a new superblock of type `fstype`.
+pub(crate) fn fsopen(fstype: &str) -> nix::Result<OwnedFd> {
+    let fstype = CString::new(fstype).map_err(|_| Errno::EINVAL)?;
+    let res = unsafe { libc::syscall(libc::SYS_fsopen, fstype.as_ptr(), FSOPEN_CLOEXEC) };
+
+    Errno::result(res).map(|fd| unsafe { OwnedFd::from_raw_fd(fd as libc::c_int) })
+}
+
+/// Sets the option `key` of the filesystem context `fd`, to `value` or as a
+/// flag.
+pub(crate) fn fsconfig(fd: BorrowedFd<'_>, key: &str, value: Option<&str>) -> nix::Result<()> {
+    let key = CString::new(key).map_err(|_| Errno::EINVAL)?;
+    let value = value
+        .map(CString::new)
+        .transpose()
+        .map_err(|_| Errno::EINVAL)?;
+
+    let res = unsafe {
+        match &value {
+            Some(value) => libc::syscall(
+                libc::SYS_fsconfig,
+                fd.as_raw_fd(),
+                FSCONFIG_SET_STRING,
+                key.as_ptr(),
+                value.as_ptr(),
+                0,
+            ),
+            None => libc::syscall(
+                libc::SYS_fsconfig,
+                fd.as_raw_fd(),
+                FSCONFIG_SET_FLAG,
+                key.as_ptr(),
+                std::ptr::null::<libc::c_char>(),
+                0,
+            ),
+        }
+    };
+
+    Errno::result(res).map(drop)
+}
+
+/// Creates the superblock of the filesystem context `fd` and returns a
+/// detached mount of it with the `MOUNT_ATTR_*` flags `attr`.
+pub(crate) fn fsmount(fd: BorrowedFd<'_>, attr: u64) -> nix::Result<OwnedFd> {
+    let res = unsafe {
+        libc::syscall(
+            libc::SYS_fsconfig,
+            fd.as_raw_fd(),
+            FSCONFIG_CMD_CREATE,
+            std::ptr::null::<libc::c_char>(),
+            std::ptr::null::<libc::c_void>(),
+            0,
+        )
+    };
+    Errno::result(res)?;
+
+    let res = unsafe { libc::syscall(libc::SYS_fsmount, fd.as_raw_fd(), FSMOUNT_CLOEXEC, attr) };
+
+    Errno::result(res).map(|fd| unsafe { OwnedFd::from_raw_fd(fd as libc::c_int) })
+}
+
+/// Reports whether the kernel implements `openat2` and `mount_setattr`, the
+/// newest of the system calls mounts are made with, by calling them with
+/// arguments they must reject.
+pub(crate) fn mount_api_available() -> bool {
+    let openat2 = unsafe {
+        libc::syscall(
+            libc::SYS_openat2,
+            -1,
+            std::ptr::null::<libc::c_char>(),
+            std::ptr::null::<OpenHow>(),
+            0,
+        )
+    };
+    let mount_setattr = unsafe {
+        libc::syscall(
+            libc::SYS_mount_setattr,
+            -1,
+            std::ptr::null::<libc::c_char>(),
+            0,
+            std::ptr::null::<MountAttr>(),
+            0,
+        )
+    };
+
+    Errno::result(openat2) != Err(Errno::ENOSYS)
+        && Errno::result(mount_setattr) != Err(Errno::ENOSYS)
+}
+
+const LOOP_CTL_GET_FREE: libc::c_ulong = 0x4C82;
+const LOOP_CONFIGURE: libc::c_ulong = 0x4C0A;
+/// Detach the loop device once it is neither open nor mounted.
+const LO_FLAGS_AUTOCLEAR: u32 = 4;
+
+/// `struct loop_info64`.
+#[repr(C)]
+struct LoopInfo64 {
+    lo_device: u64,
+    lo_inode: u64,
+    lo_rdevice: u64,
+    lo_offset: u64,
+    lo_sizelimit: u64,
+    lo_number: u32,
+    lo_encrypt_type: u32,
+    lo_encrypt_key_size: u32,
+    lo_flags: u32,
+    lo_file_name: [u8; 64],
+    lo_crypt_name: [u8; 64],
+    lo_encrypt_key: [u8; 32],
+    lo_init: [u64; 2],
+}
+
+/// `struct loop_config` of `LOOP_CONFIGURE`, Linux 5.8.
+#[repr(C)]
+struct LoopConfig {
+    fd: u32,
+    block_size: u32,
+    info: LoopInfo64,
+    reserved: [u64; 8],
+}
+
+/// Attaches `file` to a free loop device that detaches itself once it is
+/// neither open nor mounted, and returns the device path with the device
+/// open.
+pub(crate) fn loop_attach(file: BorrowedFd<'_>) -> nix::Result<(String, OwnedFd)> {
+    let control = open_cloexec("/dev/loop-control")?;
+
+    // Another process may take the free device first.
+    loop {
+        let number =
+            Errno::result(unsafe { libc::ioctl(control.as_raw_fd(), LOOP_CTL_GET_FREE as _) })?;
+        let path = format!("/dev/loop{}", number);
+        let device = open_cloexec(&path)?;
+
+        let mut config: LoopConfig = unsafe { std::mem::zeroed() };
+        config.fd = file.as_raw_fd() as u32;
+        config.info.lo_flags = LO_FLAGS_AUTOCLEAR;
+
+        let res = unsafe {
+            libc::ioctl(
+                device.as_raw_fd(),
+                LOOP_CONFIGURE as _,
+                &config as *const LoopConfig,
+            )
+        };
+        match Errno::result(res) {
+            Ok(_) => return Ok((path, device)),
+            Err(Errno::EBUSY) => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+fn open_cloexec(path: &str) -> nix::Result<OwnedFd> {
+    fcntl::open(path, OFlag::O_RDWR | OFlag::O_CLOEXEC, Mode::empty())
+        .map(|fd| unsafe { OwnedFd::from_raw_fd(fd) })
+}
+
+/// Sets the extended attribute `name` of the file open at `fd`.
+pub(crate) fn fsetxattr(fd: BorrowedFd<'_>, name: &str, value: &[u8]) -> nix::Result<()> {
+    let name = CString::new(name).map_err(|_| Errno::EINVAL)?;
+    let res = unsafe {
+        libc::fsetxattr(
+            fd.as_raw_fd(),
+            name.as_ptr(),
+            value.as_ptr().cast(),
+            value.len(),
+            0,
+        )
+    };
+
+    Errno::result(res).map(drop)
+}
+
+/// Reads the extended attribute `name` of the file open at `fd` into
+/// `value`, returning its length.
+pub(crate) fn fgetxattr(fd: BorrowedFd<'_>, name: &str, value: &mut [u8]) -> nix::Result<usize> {
+    let name = CString::new(name).map_err(|_| Errno::EINVAL)?;
+    let res = unsafe {
+        libc::fgetxattr(
+            fd.as_raw_fd(),
+            name.as_ptr(),
+            value.as_mut_ptr().cast(),
+            value.len(),
+        )
+    };
+
+    Errno::result(res).map(|len| len as usize)
+}
+
+/// Reports whether the kernel implements `clone3`, by calling it with an
+/// argument size it must reject.
+pub(crate) fn clone3_available() -> bool {
+    let res = unsafe { libc::syscall(SYS_clone3, std::ptr::null_mut::<CloneArgs>(), 0) };
+
+    Errno::result(res) != Err(Errno::ENOSYS)
+}
+
+/// Returns the running kernel's `(major, minor)` version.
+pub(crate) fn kernel_version() -> Option<(u32, u32)> {
+    let release = std::fs::read_to_string("/proc/sys/kernel/osrelease").ok()?;
+    let mut parts = release
+        .trim()
+        .split(|c: char| !c.is_ascii_digit())
+        .map(|part| part.parse::<u32>());
+
+    Some((parts.next()?.ok()?, parts.next()?.ok()?))
+}
+
+/// Reports whether the kernel implements `pidfd_open`.
+pub(crate) fn pidfd_available() -> bool {
+    let res = unsafe { libc::syscall(libc::SYS_pidfd_open, libc::getpid(), 0) };
+
+    match Errno::result(res) {
+        Ok(fd) => {
+            unsafe { libc::close(fd as libc::c_int) };
+            true
+        }
+        Err(_) => false,
+    }
+}
+
+/// Returns the Landlock ABI version, or `None` when Landlock is not built in
+/// or disabled at boot.
+pub(crate) fn landlock_abi_version() -> Option<libc::c_long> {
+    const LANDLOCK_CREATE_RULESET_VERSION: u32 = 1 << 0;
+
+    let res = unsafe {
+        libc::syscall(
+            libc::SYS_landlock_create_ruleset,
+            std::ptr::null::<libc::c_void>(),
+            0,
+            LANDLOCK_CREATE_RULESET_VERSION,
+        )
+    };
+
+    Errno::result(res).ok()
+}